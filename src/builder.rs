@@ -0,0 +1,204 @@
+use std::collections::HashMap;
+
+use crate::{Instruction, Opcode, VmError};
+
+// Builds a program in Rust instead of assembly text, producing the same instructions as load_program_from_file.
+// Jumps name labels that may be defined before or after them, they are resolved by build().
+//
+//     let program = ProgramBuilder::new()
+//         .push(3)
+//         .label("loop")
+//         .print().dec().jnz("loop")
+//         .halt()
+//         .build()?;
+#[derive(Debug, Default)]
+pub struct ProgramBuilder {
+    program: Vec<Instruction>,
+    labels: HashMap<String, usize>,
+    jumps: Vec<(usize, String)>, // Instruction index and the label its operand refers to
+    duplicate_label: Option<(String, usize)>,
+}
+
+impl ProgramBuilder {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    // Appends any instruction with explicit operands
+    pub fn instruction(mut self, opcode: Opcode, operand_1: Option<i32>, operand_2: Option<i32>) -> Self {
+        self.program.push((opcode, operand_1, operand_2));
+        self
+    }
+
+    // Marks the position of the next instruction
+    pub fn label(mut self, name: &str) -> Self {
+        if self.labels.insert(name.to_string(), self.program.len()).is_some() && self.duplicate_label.is_none() {
+            self.duplicate_label = Some((name.to_string(), self.program.len()));
+        }
+        self
+    }
+
+    // Appends a jump or CALL to the given label
+    pub fn jump_to(mut self, opcode: Opcode, label: &str) -> Self {
+        self.jumps.push((self.program.len(), label.to_string()));
+        self.instruction(opcode, None, None)
+    }
+
+    pub fn push(self, value: i32) -> Self {
+        self.instruction(Opcode::PSH, Some(value), None)
+    }
+
+    pub fn push_float(self, value: f32) -> Self {
+        self.instruction(Opcode::PSHF, Some(value.to_bits() as i32), None)
+    }
+
+    pub fn pop(self) -> Self {
+        self.instruction(Opcode::POP, None, None)
+    }
+
+    pub fn dup(self) -> Self {
+        self.instruction(Opcode::DUP, None, None)
+    }
+
+    pub fn swap(self) -> Self {
+        self.instruction(Opcode::SWP, None, None)
+    }
+
+    pub fn add(self) -> Self {
+        self.instruction(Opcode::ADD, None, None)
+    }
+
+    pub fn sub(self) -> Self {
+        self.instruction(Opcode::SUB, None, None)
+    }
+
+    pub fn mul(self) -> Self {
+        self.instruction(Opcode::MUL, None, None)
+    }
+
+    pub fn div(self) -> Self {
+        self.instruction(Opcode::DIV, None, None)
+    }
+
+    pub fn rem(self) -> Self {
+        self.instruction(Opcode::MOD, None, None)
+    }
+
+    pub fn inc(self) -> Self {
+        self.instruction(Opcode::INC, None, None)
+    }
+
+    pub fn dec(self) -> Self {
+        self.instruction(Opcode::DEC, None, None)
+    }
+
+    pub fn store(self, address: i32) -> Self {
+        self.instruction(Opcode::STR, Some(address), None)
+    }
+
+    pub fn load(self, address: i32) -> Self {
+        self.instruction(Opcode::LOA, Some(address), None)
+    }
+
+    pub fn set(self, register: i32) -> Self {
+        self.instruction(Opcode::SET, Some(register), None)
+    }
+
+    pub fn get(self, register: i32) -> Self {
+        self.instruction(Opcode::GET, Some(register), None)
+    }
+
+    pub fn jmp(self, label: &str) -> Self {
+        self.jump_to(Opcode::JMP, label)
+    }
+
+    pub fn jez(self, label: &str) -> Self {
+        self.jump_to(Opcode::JEZ, label)
+    }
+
+    pub fn jnz(self, label: &str) -> Self {
+        self.jump_to(Opcode::JNZ, label)
+    }
+
+    pub fn jgz(self, label: &str) -> Self {
+        self.jump_to(Opcode::JGZ, label)
+    }
+
+    pub fn jlz(self, label: &str) -> Self {
+        self.jump_to(Opcode::JLZ, label)
+    }
+
+    pub fn call(self, label: &str) -> Self {
+        self.jump_to(Opcode::CALL, label)
+    }
+
+    pub fn ret(self) -> Self {
+        self.instruction(Opcode::RET, None, None)
+    }
+
+    // PRT, prints the top of the stack without popping it
+    pub fn print(self) -> Self {
+        self.instruction(Opcode::PRT, None, None)
+    }
+
+    // PPT, prints and pops the top of the stack
+    pub fn print_pop(self) -> Self {
+        self.instruction(Opcode::PPT, None, None)
+    }
+
+    pub fn print_char(self) -> Self {
+        self.instruction(Opcode::PRC, None, None)
+    }
+
+    pub fn halt(self) -> Self {
+        self.instruction(Opcode::HLT, None, None)
+    }
+
+    // Resolves the label references and returns the program for VM::load_program.
+    // Errors report the 1-based instruction index in place of a source line.
+    pub fn build(mut self) -> Result<Vec<Instruction>, VmError> {
+        if let Some((name, position)) = self.duplicate_label {
+            return Err(VmError::DuplicateLabel { name, line: position + 1 });
+        }
+        for (index, label) in self.jumps {
+            let position = match self.labels.get(&label) {
+                Some(&position) => position,
+                None => return Err(VmError::UndefinedLabel { name: label, line: index + 1 }),
+            };
+            self.program[index].1 = Some(position as i32);
+        }
+        Ok(self.program)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_util::*;
+    use crate::VM;
+
+    #[test]
+    fn countdown_loop_built_in_rust() {
+        let program = ProgramBuilder::new()
+            .push(3)
+            .label("loop")
+            .print().dec().jnz("loop")
+            .halt()
+            .build()
+            .unwrap();
+        let output = SharedOutput::default();
+        let mut vm = VM::new().with_output(output.clone());
+        vm.load_program(program);
+        vm.run().unwrap();
+        assert_eq!(output.contents(), "3\n2\n1\n");
+        assert_eq!(ints(&vm), [0]);
+    }
+
+    #[test]
+    fn unresolved_labels_are_errors() {
+        let result = ProgramBuilder::new().push(1).jmp("nowhere").build();
+        assert!(matches!(result, Err(VmError::UndefinedLabel { ref name, line: 2 }) if name == "nowhere"), "{:?}", result);
+        let result = ProgramBuilder::new().label("a").push(1).label("a").build();
+        assert!(matches!(result, Err(VmError::DuplicateLabel { ref name, line: 2 }) if name == "a"), "{:?}", result);
+    }
+}
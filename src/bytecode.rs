@@ -0,0 +1,158 @@
+use std::collections::HashMap;
+use std::io::{Read, Write};
+
+use crate::{Opcode, VmError, VM};
+
+// Bytecode layout (all integers little-endian):
+//   magic "LVMB", version byte
+//   u32 instruction count, then per instruction:
+//     opcode byte, flags byte (bit 0 = operand 1 present, bit 1 = operand 2 present), i32 operand 1, i32 operand 2
+//   u32 label count, then per label:
+//     u32 name length, UTF-8 name bytes, u32 position
+const MAGIC: &[u8; 4] = b"LVMB";
+const VERSION: u8 = 1;
+
+const OPERAND_1_PRESENT: u8 = 0b01;
+const OPERAND_2_PRESENT: u8 = 0b10;
+
+impl VM {
+    pub fn save_bytecode(&self, path: &str) -> Result<(), VmError> {
+        let mut bytes = Vec::new();
+        bytes.extend_from_slice(MAGIC);
+        bytes.push(VERSION);
+
+        bytes.extend_from_slice(&(self.program.len() as u32).to_le_bytes());
+        for &(opcode, operand_1, operand_2) in &self.program {
+            let mut flags = 0;
+            if operand_1.is_some() {
+                flags |= OPERAND_1_PRESENT;
+            }
+            if operand_2.is_some() {
+                flags |= OPERAND_2_PRESENT;
+            }
+            bytes.push(opcode.to_u8());
+            bytes.push(flags);
+            bytes.extend_from_slice(&operand_1.unwrap_or(0).to_le_bytes());
+            bytes.extend_from_slice(&operand_2.unwrap_or(0).to_le_bytes());
+        }
+
+        // Sort labels so the same program always produces the same file
+        let mut labels: Vec<(&String, &usize)> = self.labels.iter().collect();
+        labels.sort();
+        bytes.extend_from_slice(&(labels.len() as u32).to_le_bytes());
+        for (name, &position) in labels {
+            bytes.extend_from_slice(&(name.len() as u32).to_le_bytes());
+            bytes.extend_from_slice(name.as_bytes());
+            bytes.extend_from_slice(&(position as u32).to_le_bytes());
+        }
+
+        std::fs::File::create(path)?.write_all(&bytes)?;
+        Ok(())
+    }
+
+    pub fn load_bytecode(&mut self, path: &str) -> Result<(), VmError> {
+        let mut bytes = Vec::new();
+        std::fs::File::open(path)?.read_to_end(&mut bytes)?;
+        let mut reader = ByteReader { bytes: &bytes, position: 0 };
+
+        if reader.take(MAGIC.len())? != MAGIC {
+            return Err(invalid("missing LeverVM bytecode header"));
+        }
+        let version = reader.u8()?;
+        if version != VERSION {
+            return Err(invalid(&format!("unsupported version {}", version)));
+        }
+
+        let instruction_count = reader.u32()?;
+        let mut program = Vec::new();
+        for _ in 0..instruction_count {
+            let byte = reader.u8()?;
+            let opcode = Opcode::from_u8(byte).ok_or_else(|| invalid(&format!("unknown opcode byte {}", byte)))?;
+            let flags = reader.u8()?;
+            let operand_1 = reader.i32()?;
+            let operand_2 = reader.i32()?;
+            program.push((
+                opcode,
+                if flags & OPERAND_1_PRESENT != 0 { Some(operand_1) } else { None },
+                if flags & OPERAND_2_PRESENT != 0 { Some(operand_2) } else { None },
+            ));
+        }
+
+        let label_count = reader.u32()?;
+        let mut labels = HashMap::new();
+        for _ in 0..label_count {
+            let length = reader.u32()? as usize;
+            let name = String::from_utf8(reader.take(length)?.to_vec()).map_err(|_| invalid("label name is not valid UTF-8"))?;
+            let position = reader.u32()? as usize;
+            labels.insert(name, position);
+        }
+
+        if reader.position != bytes.len() {
+            return Err(invalid("trailing data after labels"));
+        }
+
+        self.labels = labels;
+        self.load_program(program);
+        Ok(())
+    }
+}
+
+fn invalid(message: &str) -> VmError {
+    VmError::InvalidBytecode { message: message.to_string() }
+}
+
+struct ByteReader<'a> {
+    bytes: &'a [u8],
+    position: usize,
+}
+
+impl<'a> ByteReader<'a> {
+    fn take(&mut self, count: usize) -> Result<&'a [u8], VmError> {
+        let end = self.position + count;
+        if end > self.bytes.len() {
+            return Err(invalid("unexpected end of file"));
+        }
+        let slice = &self.bytes[self.position..end];
+        self.position = end;
+        Ok(slice)
+    }
+
+    fn u8(&mut self) -> Result<u8, VmError> {
+        Ok(self.take(1)?[0])
+    }
+
+    fn u32(&mut self) -> Result<u32, VmError> {
+        Ok(u32::from_le_bytes(self.take(4)?.try_into().unwrap()))
+    }
+
+    fn i32(&mut self) -> Result<i32, VmError> {
+        Ok(i32::from_le_bytes(self.take(4)?.try_into().unwrap()))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_util::*;
+
+    #[test]
+    fn bytecode_round_trip() {
+        let (vm, _) = load_source("PSH 3\nloop:\nDEC\nJNZ loop\nPSHF 1.5\nADD 1 2\nCALL done\ndone:\nHLT").unwrap();
+        let path = temp_directory().join("program.lvmb");
+        let path = path.to_str().unwrap();
+        vm.save_bytecode(path).unwrap();
+
+        let mut reloaded = VM::new();
+        reloaded.load_bytecode(path).unwrap();
+        assert_eq!(reloaded.program, vm.program);
+        assert_eq!(reloaded.labels, vm.labels);
+    }
+
+    #[test]
+    fn corrupt_bytecode_is_rejected() {
+        let path = temp_directory().join("corrupt.lvmb");
+        std::fs::write(&path, b"LVMB\x01\x01\x00\x00\x00\xFF").unwrap();
+        let result = VM::new().load_bytecode(path.to_str().unwrap());
+        assert!(matches!(result, Err(VmError::InvalidBytecode { .. })), "{:?}", result);
+    }
+}
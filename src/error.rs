@@ -0,0 +1,99 @@
+use std::fmt;
+
+use crate::Opcode;
+
+#[derive(Debug)]
+pub enum VmError {
+    // Runtime errors, carrying the pc of the failing instruction
+    StackUnderflow { opcode: Opcode, pc: usize },
+    StackOverflow { limit: usize, pc: usize },
+    DivByZero { pc: usize },
+    TypeMismatch { opcode: Opcode, pc: usize },
+    ArithmeticOverflow { pc: usize },
+    InvalidRegister { index: i32, pc: usize },
+    MemoryOutOfBounds { address: i32, pc: usize },
+    AllocationFailed { size: i32, pc: usize },
+    InvalidJumpTarget { target: i32, pc: usize },
+    MissingOperand { opcode: Opcode, pc: usize },
+    InvalidInput { input: String, pc: usize },
+    InvalidCharacter { value: i32, pc: usize },
+    InvalidShift { amount: i32, pc: usize },
+    InvalidRange { low: i32, high: i32, pc: usize },
+    InvalidArgument { opcode: Opcode, value: i32, pc: usize },
+    CallStackUnderflow { pc: usize },
+    CallStackOverflow { depth: usize, pc: usize },
+    InstructionLimitExceeded { limit: u64, pc: usize },
+    TimeLimitExceeded { limit: std::time::Duration, pc: usize },
+    NonEmptyStackAtHalt { remaining: usize, pc: usize },
+
+    InvalidConfig { message: String },
+
+    // Loader errors, carrying the (1-based) source line
+    UnknownOpcode { text: String, line: usize },
+    ParseError { line: usize, message: String },
+    DuplicateLabel { name: String, line: usize },
+    UndefinedLabel { name: String, line: usize },
+    ArityMismatch { opcode: Opcode, line: usize },
+    BadOperand { token: String, line: usize },
+    InvalidBytecode { message: String },
+    InvalidMemoryImage { message: String },
+    InIncludedFile { path: std::path::PathBuf, error: Box<VmError> }, // A loader error in a file pulled in by #include
+    Io(std::io::Error),
+}
+
+impl fmt::Display for VmError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            VmError::StackUnderflow { opcode, pc } => write!(f, "Stack underflow in {:?} operation at pc {}", opcode, pc),
+            VmError::StackOverflow { limit, pc } => write!(f, "Stack overflow (max {} values) at pc {}", limit, pc),
+            VmError::DivByZero { pc } => write!(f, "Division by zero at pc {}", pc),
+            VmError::TypeMismatch { opcode, pc } => write!(f, "Mixed integer and float values in {:?} operation at pc {}", opcode, pc),
+            VmError::ArithmeticOverflow { pc } => write!(f, "Arithmetic overflow at pc {}", pc),
+            VmError::InvalidRegister { index, pc } => write!(f, "Invalid register index {} at pc {}", index, pc),
+            VmError::MemoryOutOfBounds { address, pc } => write!(f, "Memory address {} out of bounds at pc {}", address, pc),
+            VmError::AllocationFailed { size, pc } => write!(f, "Cannot allocate {} memory cells at pc {}", size, pc),
+            VmError::InvalidJumpTarget { target, pc } => write!(f, "Invalid jump target {} at pc {}", target, pc),
+            VmError::MissingOperand { opcode, pc } => write!(f, "Missing operand in {:?} operation at pc {}", opcode, pc),
+            VmError::InvalidInput { input, pc } => write!(f, "Input '{}' is not a valid integer at pc {}", input, pc),
+            VmError::InvalidCharacter { value, pc } => write!(f, "Invalid character code {} at pc {}", value, pc),
+            VmError::InvalidShift { amount, pc } => write!(f, "Invalid shift amount {} (must be 0-31) at pc {}", amount, pc),
+            VmError::InvalidRange { low, high, pc } => write!(f, "Invalid range [{}, {}) at pc {}", low, high, pc),
+            VmError::InvalidArgument { opcode, value, pc } => write!(f, "Invalid argument {} for {:?} at pc {}", value, opcode, pc),
+            VmError::CallStackUnderflow { pc } => write!(f, "RET with an empty call stack at pc {}", pc),
+            VmError::CallStackOverflow { depth, pc } => write!(f, "Call stack overflow (max depth {}) at pc {}", depth, pc),
+            VmError::InstructionLimitExceeded { limit, pc } => write!(f, "Instruction limit of {} exceeded at pc {}", limit, pc),
+            VmError::TimeLimitExceeded { limit, pc } => write!(f, "Time limit of {:?} exceeded at pc {}", limit, pc),
+            VmError::NonEmptyStackAtHalt { remaining, pc } => write!(f, "HLT with {} values left on the stack at pc {}", remaining, pc),
+            VmError::InvalidConfig { message } => write!(f, "Invalid VM configuration: {}", message),
+            VmError::UnknownOpcode { text, line } => write!(f, "Unknown opcode '{}' on line {}", text, line),
+            VmError::ParseError { line, message } => write!(f, "Parse error on line {}: {}", line, message),
+            VmError::DuplicateLabel { name, line } => write!(f, "Label '{}' defined again on line {}", name, line),
+            VmError::UndefinedLabel { name, line } => write!(f, "Undefined label '{}' on line {}", name, line),
+            VmError::ArityMismatch { opcode, line } => {
+                let counts: Vec<String> = opcode.operand_spec().iter().map(|count| count.to_string()).collect();
+                write!(f, "Wrong number of operands for {:?} on line {}, expected {}", opcode, line, counts.join(" or "))
+            },
+            VmError::BadOperand { token, line } => write!(f, "Invalid operand '{}' on line {}", token, line),
+            VmError::InvalidBytecode { message } => write!(f, "Invalid bytecode: {}", message),
+            VmError::InvalidMemoryImage { message } => write!(f, "Invalid memory image: {}", message),
+            VmError::InIncludedFile { path, error } => write!(f, "In {}: {}", path.display(), error),
+            VmError::Io(e) => write!(f, "I/O error: {}", e),
+        }
+    }
+}
+
+impl std::error::Error for VmError {
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        match self {
+            VmError::Io(e) => Some(e),
+            VmError::InIncludedFile { error, .. } => Some(error.as_ref()),
+            _ => None,
+        }
+    }
+}
+
+impl From<std::io::Error> for VmError {
+    fn from(e: std::io::Error) -> Self {
+        VmError::Io(e)
+    }
+}
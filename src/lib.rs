@@ -0,0 +1,2468 @@
+mod builder;
+mod bytecode;
+mod error;
+mod loader;
+mod memory_image;
+mod opcode;
+mod value;
+#[cfg(test)]
+mod test_util;
+
+use std::collections::{BTreeMap, HashMap, HashSet};
+use std::io::{BufRead, Write};
+use std::time::{Duration, Instant, SystemTime, UNIX_EPOCH};
+
+pub use builder::ProgramBuilder;
+pub use error::VmError;
+pub use opcode::Opcode;
+pub use value::Value;
+
+const DEFAULT_MAX_MEMORY_SIZE: usize = 1024 * 1024; // 1 MB
+const DEFAULT_REGISTER_AMOUNT: usize = 8;
+const DEFAULT_MAX_STACK_SIZE: usize = 1024 * 1024;
+const DEFAULT_MAX_CALL_DEPTH: usize = 1024;
+const DEFAULT_SEED: u64 = 0x2545_F491_4F6C_DD1D;
+
+// How ADD, SUB, MUL, DIV, POW, INC and DEC handle results that don't fit in an i32
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum OverflowMode {
+    #[default]
+    Wrapping, // Wrap around in two's complement, i32::MAX + 1 == i32::MIN
+    Checked, // Stop with an ArithmeticOverflow error
+    Saturating, // Clamp to i32::MIN or i32::MAX
+}
+
+#[derive(Debug, Clone, Copy)]
+pub struct VmConfig {
+    pub registers: usize, // Amount of registers, at least 1
+    pub max_memory: usize, // Amount of addressable memory cells
+    pub max_stack: usize, // Maximum amount of values on the stack, pushing past it is a StackOverflow error
+    pub overflow: OverflowMode,
+    pub seed: u64, // Seed for RND, the same seed always gives the same sequence
+    pub max_call_depth: usize, // Maximum amount of nested CALLs, one more is a CallStackOverflow error
+    pub strict_halt: bool, // Makes HLT fail with NonEmptyStackAtHalt if values are left on the stack
+}
+
+impl Default for VmConfig {
+    fn default() -> Self {
+        VmConfig {
+            registers: DEFAULT_REGISTER_AMOUNT,
+            max_memory: DEFAULT_MAX_MEMORY_SIZE,
+            max_stack: DEFAULT_MAX_STACK_SIZE,
+            overflow: OverflowMode::default(),
+            seed: DEFAULT_SEED,
+            max_call_depth: DEFAULT_MAX_CALL_DEPTH,
+            strict_halt: false,
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum StepOutcome {
+    Running,
+    Halted,
+}
+
+// Why the VM last stopped running
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum HaltReason {
+    Halted, // HLT
+    Exited, // EXT
+    EndOfProgram, // Ran past the last instruction
+    LimitExceeded, // run_with_limit or run_with_deadline ran out of steps or time
+    Error, // An instruction failed
+}
+
+// Execution state captured by VM::snapshot, everything except the program itself
+#[derive(Debug, Clone, PartialEq)]
+pub struct VmSnapshot {
+    stack: Vec<Value>,
+    memory: Vec<i32>,
+    registers: Vec<i32>,
+    pc: usize,
+    running: bool,
+    call_stack: Vec<usize>,
+    rng_state: u64,
+    exit_code: Option<i32>,
+    heap_cursor: usize,
+    max_address_written: Option<usize>,
+    instructions_executed: u64,
+    halt_reason: Option<HaltReason>,
+}
+
+// An opcode with its optional first and second operand
+pub type Instruction = (Opcode, Option<i32>, Option<i32>);
+
+pub struct VM {
+    config: VmConfig,
+    stack: Vec<Value>,
+    memory: Vec<i32>, // Grown on demand up to max_memory, unwritten cells read as 0
+    registers: Vec<i32>,
+    program: Vec<Instruction>,
+    pc: usize,  // Program counter
+    running: bool,
+    labels: HashMap<String, usize>,
+    call_stack: Vec<usize>, // Return addresses of active CALLs
+    rng_state: u64, // xorshift64 state for RND, never 0
+    exit_code: Option<i32>, // Set by HLT (0) and EXT
+    heap_cursor: usize, // First memory cell not reserved by ALC
+    max_address_written: Option<usize>, // Highest address stored to by the program, None before the first store
+    instructions_executed: u64, // Instructions that ran successfully since the VM was created or reset
+    halt_reason: Option<HaltReason>, // None until the VM stops for the first time
+    breakpoints: HashSet<usize>,
+    last_break_pc: Option<usize>, // Breakpoint run_until_breakpoint stopped at, cleared by the next step
+    output: Box<dyn Write>, // Where PRT, PPT, PRC, PRS and DEB write to
+    input: Option<Box<dyn BufRead>>, // Where INP reads from, stdin if not set
+    profile: Option<HashMap<Opcode, u64>>, // Executions per opcode, None unless profiling is enabled
+    trace_hook: Option<TraceHook>,
+}
+
+// Called before each instruction with its pc, opcode and the current stack
+pub type TraceHook = Box<dyn FnMut(usize, Opcode, &[Value])>;
+
+impl Default for VM {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl VM {
+    pub fn new() -> Self {
+        Self::with_config(VmConfig::default()).expect("default config is valid")
+    }
+
+    pub fn with_config(config: VmConfig) -> Result<Self, VmError> {
+        if config.registers == 0 {
+            return Err(VmError::InvalidConfig { message: "at least one register is required".to_string() });
+        }
+
+        Ok(VM {
+            config,
+            stack: Vec::new(),
+            memory: Vec::new(),
+            registers: vec![0; config.registers],
+            program: Vec::new(),
+            pc: 0,
+            running: false,
+            labels: HashMap::new(),
+            call_stack: Vec::new(),
+            rng_state: rng_seed(config.seed),
+            exit_code: None,
+            heap_cursor: 0,
+            max_address_written: None,
+            instructions_executed: 0,
+            halt_reason: None,
+            breakpoints: HashSet::new(),
+            last_break_pc: None,
+            output: Box::new(std::io::stdout()),
+            input: None,
+            profile: None,
+            trace_hook: None,
+        })
+    }
+
+    // Sends program output to the given writer instead of stdout
+    pub fn with_output(mut self, output: impl Write + 'static) -> Self {
+        self.output = Box::new(output);
+        self
+    }
+
+    // Reads INP input from the given reader instead of stdin
+    pub fn with_input(mut self, input: impl BufRead + 'static) -> Self {
+        self.input = Some(Box::new(input));
+        self
+    }
+
+    // Sets how many nested CALLs are allowed before a CallStackOverflow error
+    pub fn set_max_call_depth(&mut self, depth: usize) {
+        self.config.max_call_depth = depth;
+    }
+
+    pub fn load_program(&mut self, program: Vec<Instruction>) {
+        self.program = program;
+        self.pc = 0;
+        self.last_break_pc = None;
+    }
+
+    // Clears all execution state so the loaded program can run again from the start, keeping the program, labels and breakpoints
+    pub fn reset(&mut self) {
+        self.stack.clear();
+        self.memory.clear();
+        self.registers.fill(0);
+        self.call_stack.clear();
+        self.rng_state = rng_seed(self.config.seed);
+        self.exit_code = None;
+        self.heap_cursor = 0;
+        self.max_address_written = None;
+        self.instructions_executed = 0;
+        self.halt_reason = None;
+        self.pc = 0;
+        self.running = false;
+        self.last_break_pc = None;
+        if let Some(profile) = &mut self.profile {
+            profile.clear();
+        }
+    }
+
+    // Runs the program until it halts, stopping at the first error
+    pub fn run(&mut self) -> Result<(), VmError> {
+        self.run_with_limits(None, None)
+    }
+
+    // Like run, but fails with InstructionLimitExceeded once max_steps instructions have executed without halting
+    pub fn run_with_limit(&mut self, max_steps: u64) -> Result<(), VmError> {
+        self.run_with_limits(Some(max_steps), None)
+    }
+
+    // Like run, but fails with TimeLimitExceeded once the program has run for longer than time_limit.
+    // The clock is read before every instruction, so a blocking INP is noticed as soon as it returns.
+    pub fn run_with_deadline(&mut self, time_limit: Duration) -> Result<(), VmError> {
+        self.run_with_limits(None, Some(time_limit))
+    }
+
+    // Combines run_with_limit and run_with_deadline, stopping at whichever limit is reached first
+    pub fn run_with_limits(&mut self, max_steps: Option<u64>, time_limit: Option<Duration>) -> Result<(), VmError> {
+        let result = self.run_steps(max_steps, time_limit);
+        self.output.flush()?;
+        result
+    }
+
+    fn run_steps(&mut self, max_steps: Option<u64>, time_limit: Option<Duration>) -> Result<(), VmError> {
+        let start = Instant::now();
+        let mut steps: u64 = 0;
+        while self.pc < self.program.len() {
+            if let Some(limit) = max_steps {
+                if steps >= limit {
+                    self.running = false;
+                    self.halt_reason = Some(HaltReason::LimitExceeded);
+                    return Err(VmError::InstructionLimitExceeded { limit, pc: self.pc });
+                }
+            }
+            if let Some(limit) = time_limit {
+                if start.elapsed() > limit {
+                    self.running = false;
+                    self.halt_reason = Some(HaltReason::LimitExceeded);
+                    return Err(VmError::TimeLimitExceeded { limit, pc: self.pc });
+                }
+            }
+            steps += 1;
+            if self.step()? == StepOutcome::Halted {
+                break;
+            }
+        }
+        Ok(())
+    }
+
+    // Executes exactly one instruction at pc and advances pc
+    pub fn step(&mut self) -> Result<StepOutcome, VmError> {
+        if self.pc >= self.program.len() {
+            self.running = false;
+            // Stepping a VM that already stopped keeps the original reason
+            if self.halt_reason.is_none() {
+                self.halt_reason = Some(HaltReason::EndOfProgram);
+            }
+            return Ok(StepOutcome::Halted);
+        }
+
+        self.running = true;
+        self.halt_reason = None;
+        self.last_break_pc = None;
+        let opcode = self.program[self.pc].0;
+        if let Some(profile) = &mut self.profile {
+            *profile.entry(opcode).or_insert(0) += 1;
+        }
+        if let Some(hook) = &mut self.trace_hook {
+            hook(self.pc, opcode, &self.stack);
+        }
+        match self.execute_instruction() {
+            Ok(next_pc) => self.pc = next_pc,
+            Err(e) => {
+                self.running = false;
+                self.halt_reason = Some(HaltReason::Error);
+                return Err(e);
+            }
+        }
+        self.instructions_executed += 1;
+
+        if self.running && self.pc < self.program.len() {
+            Ok(StepOutcome::Running)
+        } else {
+            self.running = false;
+            self.halt_reason = Some(match opcode {
+                Opcode::HLT => HaltReason::Halted,
+                Opcode::EXT => HaltReason::Exited,
+                _ => HaltReason::EndOfProgram,
+            });
+            Ok(StepOutcome::Halted)
+        }
+    }
+
+    // Runs until the next breakpoint (without executing it) and returns its pc, or None if the program halted.
+    // Calling this again right after it stopped executes that breakpoint's instruction and resumes past it.
+    pub fn run_until_breakpoint(&mut self) -> Result<Option<usize>, VmError> {
+        if self.last_break_pc == Some(self.pc) && self.step()? == StepOutcome::Halted {
+            return Ok(None);
+        }
+        while !self.breakpoints.contains(&self.pc) {
+            if self.step()? == StepOutcome::Halted {
+                return Ok(None);
+            }
+        }
+        self.last_break_pc = Some(self.pc);
+        Ok(Some(self.pc))
+    }
+
+    pub fn add_breakpoint(&mut self, pc: usize) {
+        self.breakpoints.insert(pc);
+    }
+
+    // Returns false if the label doesn't exist
+    pub fn add_breakpoint_label(&mut self, name: &str) -> bool {
+        if let Some(pc) = self.resolve_label(name) {
+            self.breakpoints.insert(pc);
+            true
+        } else {
+            false
+        }
+    }
+
+    // Returns false if there was no breakpoint at pc
+    pub fn remove_breakpoint(&mut self, pc: usize) -> bool {
+        self.breakpoints.remove(&pc)
+    }
+
+    pub fn set_trace_hook(&mut self, hook: TraceHook) {
+        self.trace_hook = Some(hook);
+    }
+
+    pub fn clear_trace_hook(&mut self) {
+        self.trace_hook = None;
+    }
+
+    // Starts counting how many times each opcode is executed
+    pub fn enable_profiling(&mut self) {
+        if self.profile.is_none() {
+            self.profile = Some(HashMap::new());
+        }
+    }
+
+    // Returns the executed opcodes with their counts, most executed first (empty if profiling is disabled)
+    pub fn profile_report(&self) -> Vec<(Opcode, u64)> {
+        let mut report: Vec<(Opcode, u64)> = self.profile.iter().flatten().map(|(&opcode, &count)| (opcode, count)).collect();
+        report.sort_by_key(|&(opcode, count)| (std::cmp::Reverse(count), opcode.to_u8()));
+        report
+    }
+
+    pub fn snapshot(&self) -> VmSnapshot {
+        VmSnapshot {
+            stack: self.stack.clone(),
+            memory: self.memory.clone(),
+            registers: self.registers.clone(),
+            pc: self.pc,
+            running: self.running,
+            call_stack: self.call_stack.clone(),
+            rng_state: self.rng_state,
+            exit_code: self.exit_code,
+            heap_cursor: self.heap_cursor,
+            max_address_written: self.max_address_written,
+            instructions_executed: self.instructions_executed,
+            halt_reason: self.halt_reason,
+        }
+    }
+
+    pub fn restore(&mut self, snapshot: &VmSnapshot) {
+        self.stack = snapshot.stack.clone();
+        self.memory = snapshot.memory.clone();
+        self.registers = snapshot.registers.clone();
+        self.pc = snapshot.pc;
+        self.running = snapshot.running;
+        self.call_stack = snapshot.call_stack.clone();
+        self.rng_state = snapshot.rng_state;
+        self.exit_code = snapshot.exit_code;
+        self.heap_cursor = snapshot.heap_cursor;
+        self.max_address_written = snapshot.max_address_written;
+        self.instructions_executed = snapshot.instructions_executed;
+        self.halt_reason = snapshot.halt_reason;
+        self.last_break_pc = None;
+    }
+
+    // The code the program halted with: 0 after HLT, the popped value after EXT, None if neither ran
+    pub fn exit_code(&self) -> Option<i32> {
+        self.exit_code
+    }
+
+    // Instructions that ran successfully since the VM was created or last reset, across every run and step call
+    pub fn instructions_executed(&self) -> u64 {
+        self.instructions_executed
+    }
+
+    // Why the VM last stopped, None if it hasn't stopped yet
+    pub fn halt_reason(&self) -> Option<HaltReason> {
+        self.halt_reason
+    }
+
+    // The highest memory address the program has stored to (through STR, STRI, STRR, FIL or MCP) since the VM
+    // was created or reset, None if it hasn't stored anything. Useful for choosing VmConfig::max_memory
+    pub fn memory_high_water(&self) -> Option<usize> {
+        self.max_address_written
+    }
+
+    pub fn pc(&self) -> usize {
+        self.pc
+    }
+
+    // The stack from bottom to top, e.g. to read a program's results after run
+    pub fn stack(&self) -> &[Value] {
+        &self.stack
+    }
+
+    pub fn top(&self) -> Option<Value> {
+        self.stack.last().copied()
+    }
+
+    pub fn registers(&self) -> &[i32] {
+        &self.registers
+    }
+
+    // Label names of the loaded program mapped to the index of the instruction they mark
+    pub fn labels(&self) -> &HashMap<String, usize> {
+        &self.labels
+    }
+
+    pub fn resolve_label(&self, name: &str) -> Option<usize> {
+        self.labels.get(name).copied()
+    }
+
+    // The closest label at or before pc, for showing which function a pc is in.
+    // When several labels mark the same instruction the alphabetically first one is returned, like in disassemble.
+    pub fn label_for_pc(&self, pc: usize) -> Option<&str> {
+        self.labels.iter()
+            .filter(|(_, &position)| position <= pc)
+            .max_by_key(|(name, &position)| (position, std::cmp::Reverse(name.as_str())))
+            .map(|(name, _)| name.as_str())
+    }
+
+    fn execute_instruction(&mut self) -> Result<usize, VmError> {
+        let (opcode, operand_1, operand_2) = self.program[self.pc];
+
+        match opcode {
+            Opcode::ADD => {
+                if let Some(operand_2) = operand_2 { // Use register ADD if there is a second operand
+                    let (a, b) = self.register_pair(operand_1.unwrap_or(0), operand_2)?;
+                    let result = self.apply_overflow(a as i64 + b as i64)?;
+                    self.push(Value::Int(result))?;
+                } else { // Otherwise use stack ADD
+                    let (a, b) = self.pop_pair(opcode)?;
+                    let result = self.apply_overflow(a as i64 + b as i64)?;
+                    self.push(Value::Int(result))?;
+                }
+            },
+            Opcode::SUB => {
+                if let Some(operand_2) = operand_2 {
+                    let (a, b) = self.register_pair(operand_1.unwrap_or(0), operand_2)?;
+                    let result = self.apply_overflow(a as i64 - b as i64)?;
+                    self.push(Value::Int(result))?;
+                } else {
+                    let (a, b) = self.pop_pair(opcode)?;
+                    let result = self.apply_overflow(a as i64 - b as i64)?;
+                    self.push(Value::Int(result))?;
+                }
+            },
+            Opcode::MUL => {
+                if let Some(operand_2) = operand_2 {
+                    let (a, b) = self.register_pair(operand_1.unwrap_or(0), operand_2)?;
+                    let result = self.apply_overflow(a as i64 * b as i64)?;
+                    self.push(Value::Int(result))?;
+                } else {
+                    let (a, b) = self.pop_pair(opcode)?;
+                    let result = self.apply_overflow(a as i64 * b as i64)?;
+                    self.push(Value::Int(result))?;
+                }
+            },
+            Opcode::DIV => {
+                if let Some(operand_2) = operand_2 {
+                    let (a, b) = self.register_pair(operand_1.unwrap_or(0), operand_2)?;
+                    if b == 0 {
+                        return Err(VmError::DivByZero { pc: self.pc });
+                    }
+                    // Computed in i64 so i32::MIN / -1 goes through the overflow mode instead of panicking
+                    let result = self.apply_overflow(a as i64 / b as i64)?;
+                    self.push(Value::Int(result))?;
+                } else {
+                    let (a, b) = self.pop_pair(opcode)?;
+                    if b == 0 {
+                        return Err(VmError::DivByZero { pc: self.pc });
+                    }
+                    let result = self.apply_overflow(a as i64 / b as i64)?;
+                    self.push(Value::Int(result))?;
+                }
+            },
+            Opcode::MOD => {
+                if let Some(operand_2) = operand_2 {
+                    let (a, b) = self.register_pair(operand_1.unwrap_or(0), operand_2)?;
+                    if b == 0 {
+                        return Err(VmError::DivByZero { pc: self.pc });
+                    }
+                    // i32::MIN % -1 panics in i32 but is simply 0 in i64
+                    let result = self.apply_overflow(a as i64 % b as i64)?;
+                    self.push(Value::Int(result))?;
+                } else {
+                    let (a, b) = self.pop_pair(opcode)?;
+                    if b == 0 {
+                        return Err(VmError::DivByZero { pc: self.pc });
+                    }
+                    let result = self.apply_overflow(a as i64 % b as i64)?;
+                    self.push(Value::Int(result))?;
+                }
+            },
+            Opcode::INC => {
+                if let Some(register) = operand_1 {
+                    let register = self.register_index(register)?;
+                    self.registers[register] = self.apply_overflow(self.registers[register] as i64 + 1)?;
+                } else {
+                    let a = self.pop_value(opcode)?;
+                    let result = self.apply_overflow(a as i64 + 1)?;
+                    self.push(Value::Int(result))?;
+                }
+            },
+            Opcode::DEC => {
+                if let Some(register) = operand_1 {
+                    let register = self.register_index(register)?;
+                    self.registers[register] = self.apply_overflow(self.registers[register] as i64 - 1)?;
+                } else {
+                    let a = self.pop_value(opcode)?;
+                    let result = self.apply_overflow(a as i64 - 1)?;
+                    self.push(Value::Int(result))?;
+                }
+            },
+            Opcode::NEG => {
+                if let Some(register) = operand_1 {
+                    let register = self.register_index(register)?;
+                    self.registers[register] = self.apply_overflow(-(self.registers[register] as i64))?;
+                } else {
+                    let a = self.pop_value(opcode)?;
+                    let result = self.apply_overflow(-(a as i64))?;
+                    self.push(Value::Int(result))?;
+                }
+            },
+            Opcode::ABS => {
+                if let Some(register) = operand_1 {
+                    let register = self.register_index(register)?;
+                    self.registers[register] = self.apply_overflow((self.registers[register] as i64).abs())?;
+                } else {
+                    let a = self.pop_value(opcode)?;
+                    let result = self.apply_overflow((a as i64).abs())?;
+                    self.push(Value::Int(result))?;
+                }
+            },
+            Opcode::MIN => {
+                if let Some(operand_2) = operand_2 {
+                    let (a, b) = self.register_pair(operand_1.unwrap_or(0), operand_2)?;
+                    self.push(Value::Int(a.min(b)))?;
+                } else {
+                    let (a, b) = self.pop_pair(opcode)?;
+                    self.push(Value::Int(a.min(b)))?;
+                }
+            },
+            Opcode::MAX => {
+                if let Some(operand_2) = operand_2 {
+                    let (a, b) = self.register_pair(operand_1.unwrap_or(0), operand_2)?;
+                    self.push(Value::Int(a.max(b)))?;
+                } else {
+                    let (a, b) = self.pop_pair(opcode)?;
+                    self.push(Value::Int(a.max(b)))?;
+                }
+            },
+            Opcode::POW => {
+                if let Some(operand_2) = operand_2 {
+                    let (base, exponent) = self.register_pair(operand_1.unwrap_or(0), operand_2)?;
+                    let result = self.power(opcode, base, exponent)?;
+                    self.push(Value::Int(result))?;
+                } else {
+                    let (base, exponent) = self.pop_pair(opcode)?;
+                    let result = self.power(opcode, base, exponent)?;
+                    self.push(Value::Int(result))?;
+                }
+            },
+            Opcode::GCD => {
+                if let Some(operand_2) = operand_2 {
+                    let (a, b) = self.register_pair(operand_1.unwrap_or(0), operand_2)?;
+                    let result = self.gcd(a, b)?;
+                    self.push(Value::Int(result))?;
+                } else {
+                    let (a, b) = self.pop_pair(opcode)?;
+                    let result = self.gcd(a, b)?;
+                    self.push(Value::Int(result))?;
+                }
+            },
+            Opcode::SQR => {
+                // checked_isqrt is an exact integer algorithm and only fails for negative values
+                if let Some(register) = operand_1 {
+                    let register = self.register_index(register)?;
+                    let value = self.registers[register];
+                    self.registers[register] = value.checked_isqrt().ok_or(VmError::InvalidArgument { opcode, value, pc: self.pc })?;
+                } else {
+                    let value = self.pop_value(opcode)?;
+                    let result = value.checked_isqrt().ok_or(VmError::InvalidArgument { opcode, value, pc: self.pc })?;
+                    self.push(Value::Int(result))?;
+                }
+            },
+            Opcode::AND => {
+                if let Some(operand_2) = operand_2 {
+                    let (a, b) = self.register_pair(operand_1.unwrap_or(0), operand_2)?;
+                    self.push(Value::Int(a & b))?;
+                } else {
+                    let (a, b) = self.pop_pair(opcode)?;
+                    self.push(Value::Int(a & b))?;
+                }
+            },
+            Opcode::OR => {
+                if let Some(operand_2) = operand_2 {
+                    let (a, b) = self.register_pair(operand_1.unwrap_or(0), operand_2)?;
+                    self.push(Value::Int(a | b))?;
+                } else {
+                    let (a, b) = self.pop_pair(opcode)?;
+                    self.push(Value::Int(a | b))?;
+                }
+            },
+            Opcode::XOR => {
+                if let Some(operand_2) = operand_2 {
+                    let (a, b) = self.register_pair(operand_1.unwrap_or(0), operand_2)?;
+                    self.push(Value::Int(a ^ b))?;
+                } else {
+                    let (a, b) = self.pop_pair(opcode)?;
+                    self.push(Value::Int(a ^ b))?;
+                }
+            },
+            Opcode::NND => {
+                if let Some(operand_2) = operand_2 {
+                    let (a, b) = self.register_pair(operand_1.unwrap_or(0), operand_2)?;
+                    self.push(Value::Int(!(a & b)))?;
+                } else {
+                    let (a, b) = self.pop_pair(opcode)?;
+                    self.push(Value::Int(!(a & b)))?;
+                }
+            },
+            Opcode::NOR => {
+                if let Some(operand_2) = operand_2 {
+                    let (a, b) = self.register_pair(operand_1.unwrap_or(0), operand_2)?;
+                    self.push(Value::Int(!(a | b)))?;
+                } else {
+                    let (a, b) = self.pop_pair(opcode)?;
+                    self.push(Value::Int(!(a | b)))?;
+                }
+            },
+            Opcode::XNR => {
+                if let Some(operand_2) = operand_2 {
+                    let (a, b) = self.register_pair(operand_1.unwrap_or(0), operand_2)?;
+                    self.push(Value::Int(!(a ^ b)))?;
+                } else {
+                    let (a, b) = self.pop_pair(opcode)?;
+                    self.push(Value::Int(!(a ^ b)))?;
+                }
+            },
+            Opcode::SHL => {
+                let (value, amount) = if let Some(operand_2) = operand_2 {
+                    self.register_pair(operand_1.unwrap_or(0), operand_2)?
+                } else {
+                    self.pop_pair(opcode)?
+                };
+                let amount = self.shift_amount(amount)?;
+                self.push(Value::Int(value << amount))?;
+            },
+            Opcode::SHR => {
+                let (value, amount) = if let Some(operand_2) = operand_2 {
+                    self.register_pair(operand_1.unwrap_or(0), operand_2)?
+                } else {
+                    self.pop_pair(opcode)?
+                };
+                let amount = self.shift_amount(amount)?;
+                self.push(Value::Int(value >> amount))?;
+            },
+            Opcode::NOT => {
+                if let Some(register) = operand_1 {
+                    let register = self.register_index(register)?;
+                    self.registers[register] = !self.registers[register];
+                } else {
+                    let a = self.pop_value(opcode)?;
+                    self.push(Value::Int(!a))?;
+                }
+            },
+            Opcode::PSH => {
+                if let Some(value) = operand_1 {
+                    self.push(Value::Int(value))?;
+                }
+            },
+            Opcode::PSHF => {
+                if let Some(bits) = operand_1 {
+                    self.push(Value::Float(f32::from_bits(bits as u32)))?;
+                }
+            },
+            Opcode::FADD => {
+                let (a, b) = self.pop_float_pair(opcode)?;
+                self.push(Value::Float(a + b))?;
+            },
+            Opcode::FSUB => {
+                let (a, b) = self.pop_float_pair(opcode)?;
+                self.push(Value::Float(a - b))?;
+            },
+            Opcode::FMUL => {
+                let (a, b) = self.pop_float_pair(opcode)?;
+                self.push(Value::Float(a * b))?;
+            },
+            Opcode::FDIV => {
+                // Follows IEEE 754, x / 0.0 is +/-infinity and 0.0 / 0.0 is NaN
+                let (a, b) = self.pop_float_pair(opcode)?;
+                self.push(Value::Float(a / b))?;
+            },
+            Opcode::ITOF => {
+                let a = self.pop_value(opcode)?;
+                self.push(Value::Float(a as f32))?;
+            },
+            Opcode::FTOI => {
+                match self.stack.last().copied() {
+                    Some(Value::Float(a)) => {
+                        // Saturates at the i32 bounds and turns NaN into 0
+                        self.stack.pop();
+                        self.push(Value::Int(a as i32))?;
+                    },
+                    Some(Value::Int(_)) => return Err(VmError::TypeMismatch { opcode, pc: self.pc }),
+                    None => return Err(VmError::StackUnderflow { opcode, pc: self.pc }),
+                }
+            },
+            Opcode::POP => {
+                self.pop_any(opcode)?;
+            },
+            Opcode::STR => {
+                // Validate the address before popping so the stack is untouched on error
+                if let Some(address) = operand_1 {
+                    let address = self.memory_address(address)?;
+                    let value = self.pop_value(opcode)?;
+                    self.write_memory(address, value);
+                } else {
+                    return Err(VmError::MissingOperand { opcode, pc: self.pc });
+                }
+            },
+            Opcode::LOA => {
+                if let Some(address) = operand_1 {
+                    let address = self.memory_address(address)?;
+                    self.push(Value::Int(self.read_memory(address)))?;
+                }
+            },
+            Opcode::STRI => {
+                // Validate the address before popping so the stack is untouched on error
+                let (_, address) = self.peek_pair(opcode)?;
+                let address = self.memory_address(address.as_int().ok_or(VmError::TypeMismatch { opcode, pc: self.pc })?)?;
+                let (value, _) = self.pop_pair(opcode)?;
+                self.write_memory(address, value);
+            },
+            Opcode::LOAI => {
+                let address = self.pop_value(opcode)?;
+                let address = self.memory_address(address)?;
+                self.push(Value::Int(self.read_memory(address)))?;
+            },
+            Opcode::STRR => {
+                if let Some(reg) = operand_1 {
+                    let address = self.registers[self.register_index(reg)?];
+                    let address = self.memory_address(address)?;
+                    let value = self.pop_value(opcode)?;
+                    self.write_memory(address, value);
+                } else {
+                    return Err(VmError::MissingOperand { opcode, pc: self.pc });
+                }
+            },
+            Opcode::LOAR => {
+                if let Some(reg) = operand_1 {
+                    let address = self.registers[self.register_index(reg)?];
+                    let address = self.memory_address(address)?;
+                    self.push(Value::Int(self.read_memory(address)))?;
+                } else {
+                    return Err(VmError::MissingOperand { opcode, pc: self.pc });
+                }
+            },
+            Opcode::DUP => {
+                let a = self.stack.last().copied().ok_or(VmError::StackUnderflow { opcode, pc: self.pc })?;
+                self.push(a)?;
+            },
+            Opcode::SWP => {
+                let len = self.stack.len();
+                if len < 2 {
+                    return Err(VmError::StackUnderflow { opcode, pc: self.pc });
+                }
+                self.stack.swap(len - 2, len - 1);
+            },
+            Opcode::PEK => {
+                if let Some(depth) = operand_1 {
+                    let value = usize::try_from(depth).ok()
+                        .and_then(|depth| self.stack.len().checked_sub(depth + 1))
+                        .map(|index| self.stack[index])
+                        .ok_or(VmError::StackUnderflow { opcode, pc: self.pc })?;
+                    self.push(value)?;
+                } else {
+                    return Err(VmError::MissingOperand { opcode, pc: self.pc });
+                }
+            },
+            Opcode::ROT => {
+                let len = self.stack.len();
+                if len < 3 {
+                    return Err(VmError::StackUnderflow { opcode, pc: self.pc });
+                }
+                self.stack[len - 3..].rotate_left(1);
+            },
+            Opcode::OVER => {
+                let (a, _) = self.peek_pair(opcode)?;
+                self.push(a)?;
+            },
+            Opcode::DRP => {
+                if let Some(count) = operand_1 {
+                    let remaining = usize::try_from(count).ok()
+                        .and_then(|count| self.stack.len().checked_sub(count))
+                        .ok_or(VmError::StackUnderflow { opcode, pc: self.pc })?;
+                    self.stack.truncate(remaining);
+                } else {
+                    return Err(VmError::MissingOperand { opcode, pc: self.pc });
+                }
+            },
+            Opcode::DPN => {
+                if let Some(count) = operand_1 {
+                    let start = usize::try_from(count).ok()
+                        .and_then(|count| self.stack.len().checked_sub(count))
+                        .ok_or(VmError::StackUnderflow { opcode, pc: self.pc })?;
+                    if self.stack.len() * 2 - start > self.config.max_stack {
+                        return Err(VmError::StackOverflow { limit: self.config.max_stack, pc: self.pc });
+                    }
+                    self.stack.extend_from_within(start..);
+                } else {
+                    return Err(VmError::MissingOperand { opcode, pc: self.pc });
+                }
+            },
+            Opcode::SEL => {
+                // The else-value is pushed first and the condition last, so the stack reads [else, then, condition]
+                let len = self.stack.len();
+                if len < 3 {
+                    return Err(VmError::StackUnderflow { opcode, pc: self.pc });
+                }
+                let selected = if self.peek_int(opcode)? != Some(0) { self.stack[len - 2] } else { self.stack[len - 3] };
+                self.stack.truncate(len - 3);
+                self.push(selected)?;
+            },
+            Opcode::LEN => {
+                self.push(Value::Int(self.stack.len() as i32))?;
+            },
+            Opcode::SCL => {
+                if self.stack.is_empty() {
+                    eprintln!("Warning: Stack is already empty in SCL operation!");
+                } else {
+                    self.stack.clear();
+                }
+            },
+            Opcode::SET => {
+                if let Some(reg) = operand_1 {
+                    let reg = self.register_index(reg)?;
+                    self.registers[reg] = self.pop_value(opcode)?;
+                } else if self.stack.is_empty() {
+                    return Err(VmError::StackUnderflow { opcode, pc: self.pc });
+                }
+            },
+            Opcode::GET => {
+                if let Some(reg) = operand_1 {
+                    let value = self.registers[self.register_index(reg)?];
+                    self.push(Value::Int(value))?;
+                }
+            }
+            Opcode::GETI => {
+                // Validate the index before popping so the stack is untouched on error
+                let index = self.peek_int(opcode)?.ok_or(VmError::StackUnderflow { opcode, pc: self.pc })?;
+                let value = self.registers[self.register_index(index)?];
+                self.stack.pop();
+                self.push(Value::Int(value))?;
+            },
+            Opcode::SETI => {
+                let (_, index) = self.peek_pair(opcode)?;
+                let reg = self.register_index(index.as_int().ok_or(VmError::TypeMismatch { opcode, pc: self.pc })?)?;
+                let (value, _) = self.pop_pair(opcode)?;
+                self.registers[reg] = value;
+            },
+            Opcode::INP => {
+                let mut input_line = String::new();
+                self.read_input_line(&mut input_line)?;
+                let a: i32 = match input_line.trim().parse() {
+                    Ok(val) => val,
+                    Err(_) => {
+                        return Err(VmError::InvalidInput { input: input_line.trim().to_string(), pc: self.pc });
+                    }
+                };
+                self.push(Value::Int(a))?;
+            },
+            Opcode::INPS => {
+                let mut input_line = String::new();
+                self.read_input_line(&mut input_line)?;
+                let text = input_line.strip_suffix('\n').unwrap_or(&input_line);
+                let text = text.strip_suffix('\r').unwrap_or(text);
+                let codes: Vec<i32> = text.chars().map(|ch| ch as i32).collect();
+                if self.stack.len() + codes.len() + 1 > self.config.max_stack {
+                    return Err(VmError::StackOverflow { limit: self.config.max_stack, pc: self.pc });
+                }
+                // The 0 marks the end of the text once it has been popped off character by character
+                self.push(Value::Int(0))?;
+                for &code in codes.iter().rev() {
+                    self.push(Value::Int(code))?;
+                }
+            },
+            Opcode::PRT => {
+                // Peeks, so the printed value stays on the stack
+                let value = *self.stack.last().ok_or(VmError::StackUnderflow { opcode, pc: self.pc })?;
+                writeln!(self.output, "{}", value)?;
+            },
+            Opcode::PPT => {
+                let value = self.pop_any(opcode)?;
+                writeln!(self.output, "{}", value)?;
+            },
+            Opcode::PRU => {
+                let value = self.peek_int(opcode)?.ok_or(VmError::StackUnderflow { opcode, pc: self.pc })?;
+                writeln!(self.output, "{}", value as u32)?;
+            },
+            Opcode::PRX => {
+                let value = self.peek_int(opcode)?.ok_or(VmError::StackUnderflow { opcode, pc: self.pc })?;
+                writeln!(self.output, "{:#010X}", value as u32)?;
+            },
+            Opcode::PRC => {
+                let value = self.pop_value(opcode)?;
+                if let Some(ch) = char::from_u32(value as u32) {
+                    write!(self.output, "{}", ch)?;
+                } else {
+                    return Err(VmError::InvalidCharacter { value, pc: self.pc });
+                }
+            },
+            Opcode::PRS => {
+                if let Some(address) = operand_1 {
+                    let mut address = self.memory_address(address)?;
+                    let mut text = String::new();
+                    loop {
+                        let value = self.read_memory(address);
+                        if value == 0 {
+                            break;
+                        }
+                        let ch = char::from_u32(value as u32).ok_or(VmError::InvalidCharacter { value, pc: self.pc })?;
+                        text.push(ch);
+                        address = self.memory_address(address as i32 + 1)?;
+                    }
+                    write!(self.output, "{}", text)?;
+                } else {
+                    return Err(VmError::MissingOperand { opcode, pc: self.pc });
+                }
+            },
+            Opcode::DEB => {
+                self.debug_state()?;
+            },
+            Opcode::DMP => {
+                if let (Some(start), Some(count)) = (operand_1, operand_2) {
+                    let range = self.memory_range(start, count)?;
+                    let cells: Vec<String> = range.clone().map(|address| self.read_memory(address).to_string()).collect();
+                    writeln!(self.output, "Memory[{}..{}]: [{}]", range.start, range.end, cells.join(", "))?;
+                } else {
+                    return Err(VmError::MissingOperand { opcode, pc: self.pc });
+                }
+            },
+            Opcode::HLT => {
+                if self.config.strict_halt && !self.stack.is_empty() {
+                    return Err(VmError::NonEmptyStackAtHalt { remaining: self.stack.len(), pc: self.pc });
+                }
+                // Move pc past the end so stepping a halted VM stays halted
+                self.running = false;
+                self.exit_code = Some(0);
+                return Ok(self.program.len());
+            },
+            Opcode::EXT => {
+                self.exit_code = Some(self.pop_value(opcode)?);
+                self.running = false;
+                return Ok(self.program.len());
+            },
+            Opcode::NOP | Opcode::LBL => {
+                // Does nothing
+            },
+            Opcode::JMP => {
+                if let Some(target) = operand_1 {
+                    return self.jump_target(target);
+                }
+            },
+            Opcode::JEZ => {
+                if let Some(value) = self.peek_int(opcode)? {
+                    if value == 0 {
+                        if let Some(target) = operand_1 {
+                            return self.jump_target(target);
+                        }
+                    }
+                }
+            },
+            Opcode::JNZ => {
+                if let Some(value) = self.peek_int(opcode)? {
+                    if value != 0 {
+                        if let Some(target) = operand_1 {
+                            return self.jump_target(target);
+                        }
+                    }
+                }
+            },
+            Opcode::JGZ => {
+                if let Some(value) = self.peek_int(opcode)? {
+                    if value > 0 {
+                        if let Some(target) = operand_1 {
+                            return self.jump_target(target);
+                        }
+                    }
+                }
+            },
+            Opcode::JLZ => {
+                if let Some(value) = self.peek_int(opcode)? {
+                    if value < 0 {
+                        if let Some(target) = operand_1 {
+                            return self.jump_target(target);
+                        }
+                    }
+                }
+            },
+            Opcode::JEZP => {
+                let value = self.pop_value(opcode)?;
+                if value == 0 {
+                    if let Some(target) = operand_1 {
+                        return self.jump_target(target);
+                    }
+                }
+            },
+            Opcode::JNZP => {
+                let value = self.pop_value(opcode)?;
+                if value != 0 {
+                    if let Some(target) = operand_1 {
+                        return self.jump_target(target);
+                    }
+                }
+            },
+            Opcode::JGZP => {
+                let value = self.pop_value(opcode)?;
+                if value > 0 {
+                    if let Some(target) = operand_1 {
+                        return self.jump_target(target);
+                    }
+                }
+            },
+            Opcode::JLZP => {
+                let value = self.pop_value(opcode)?;
+                if value < 0 {
+                    if let Some(target) = operand_1 {
+                        return self.jump_target(target);
+                    }
+                }
+            },
+            Opcode::JMR => {
+                if let Some(offset) = operand_1 {
+                    return self.jump_target((self.pc as i64 + offset as i64) as i32);
+                }
+            },
+            Opcode::JMI => {
+                let target = self.pop_value(opcode)?;
+                return self.jump_target(target);
+            },
+            Opcode::CALL => {
+                if let Some(target) = operand_1 {
+                    let target = self.jump_target(target)?;
+                    if self.call_stack.len() >= self.config.max_call_depth {
+                        return Err(VmError::CallStackOverflow { depth: self.config.max_call_depth, pc: self.pc });
+                    }
+                    self.call_stack.push(self.pc + 1);
+                    return Ok(target);
+                } else {
+                    return Err(VmError::MissingOperand { opcode, pc: self.pc });
+                }
+            },
+            Opcode::RET => {
+                if let Some(return_address) = self.call_stack.pop() {
+                    return Ok(return_address);
+                } else {
+                    return Err(VmError::CallStackUnderflow { pc: self.pc });
+                }
+            },
+            Opcode::EQU => {
+                if let Some(operand_2) = operand_2 {
+                    let (a, b) = self.register_pair(operand_1.unwrap_or(0), operand_2)?;
+                    let result = if a == b { 1 } else { 0 };
+                    self.push(Value::Int(result))?;
+                } else {
+                    let (a, b) = self.pop_pair(opcode)?;
+                    let result = if a == b { 1 } else { 0 };
+                    self.push(Value::Int(result))?;
+                }
+            },
+            Opcode::NEQ => {
+                if let Some(operand_2) = operand_2 {
+                    let (a, b) = self.register_pair(operand_1.unwrap_or(0), operand_2)?;
+                    let result = if a != b { 1 } else { 0 };
+                    self.push(Value::Int(result))?;
+                } else {
+                    let (a, b) = self.pop_pair(opcode)?;
+                    let result = if a != b { 1 } else { 0 };
+                    self.push(Value::Int(result))?;
+                }
+            },
+            Opcode::GTH => {
+                if let Some(operand_2) = operand_2 {
+                    let (a, b) = self.register_pair(operand_1.unwrap_or(0), operand_2)?;
+                    let result = if a > b { 1 } else { 0 };
+                    self.push(Value::Int(result))?;
+                } else {
+                    let (a, b) = self.pop_pair(opcode)?;
+                    let result = if a > b { 1 } else { 0 };
+                    self.push(Value::Int(result))?;
+                }
+            },
+            Opcode::LTH => {
+                if let Some(operand_2) = operand_2 {
+                    let (a, b) = self.register_pair(operand_1.unwrap_or(0), operand_2)?;
+                    let result = if a < b { 1 } else { 0 };
+                    self.push(Value::Int(result))?;
+                } else {
+                    let (a, b) = self.pop_pair(opcode)?;
+                    let result = if a < b { 1 } else { 0 };
+                    self.push(Value::Int(result))?;
+                }
+            },
+            Opcode::GTE => {
+                if let Some(operand_2) = operand_2 {
+                    let (a, b) = self.register_pair(operand_1.unwrap_or(0), operand_2)?;
+                    let result = if a >= b { 1 } else { 0 };
+                    self.push(Value::Int(result))?;
+                } else {
+                    let (a, b) = self.pop_pair(opcode)?;
+                    let result = if a >= b { 1 } else { 0 };
+                    self.push(Value::Int(result))?;
+                }
+            },
+            Opcode::LTE => {
+                if let Some(operand_2) = operand_2 {
+                    let (a, b) = self.register_pair(operand_1.unwrap_or(0), operand_2)?;
+                    let result = if a <= b { 1 } else { 0 };
+                    self.push(Value::Int(result))?;
+                } else {
+                    let (a, b) = self.pop_pair(opcode)?;
+                    let result = if a <= b { 1 } else { 0 };
+                    self.push(Value::Int(result))?;
+                }
+            },
+            Opcode::ANL => {
+                if let Some(operand_2) = operand_2 {
+                    let (a, b) = self.register_pair(operand_1.unwrap_or(0), operand_2)?;
+                    let result = if a != 0 && b != 0 { 1 } else { 0 };
+                    self.push(Value::Int(result))?;
+                } else {
+                    let (a, b) = self.pop_pair(opcode)?;
+                    let result = if a != 0 && b != 0 { 1 } else { 0 };
+                    self.push(Value::Int(result))?;
+                }
+            },
+            Opcode::ORL => {
+                if let Some(operand_2) = operand_2 {
+                    let (a, b) = self.register_pair(operand_1.unwrap_or(0), operand_2)?;
+                    let result = if a != 0 || b != 0 { 1 } else { 0 };
+                    self.push(Value::Int(result))?;
+                } else {
+                    let (a, b) = self.pop_pair(opcode)?;
+                    let result = if a != 0 || b != 0 { 1 } else { 0 };
+                    self.push(Value::Int(result))?;
+                }
+            },
+            Opcode::MCL => {
+                if self.memory.is_empty() {
+                    eprintln!("Warning: Memory is already clear in MCL operation!")
+                } else {
+                    self.memory.clear();
+                }
+            },
+            Opcode::ALC => {
+                if let Some(size) = operand_1 {
+                    let base = self.heap_cursor;
+                    let end = usize::try_from(size).ok()
+                        .map(|size| base + size)
+                        .filter(|&end| end <= self.config.max_memory)
+                        .ok_or(VmError::AllocationFailed { size, pc: self.pc })?;
+                    // Regions can be handed out again after FRE, so clear what a previous owner left behind
+                    let written = end.min(self.memory.len());
+                    if base < written {
+                        self.memory[base..written].fill(0);
+                    }
+                    self.push(Value::Int(base as i32))?;
+                    self.heap_cursor = end;
+                } else {
+                    return Err(VmError::MissingOperand { opcode, pc: self.pc });
+                }
+            },
+            Opcode::FRE => {
+                self.heap_cursor = 0;
+            },
+            Opcode::FIL => {
+                if let (Some(start), Some(count)) = (operand_1, operand_2) {
+                    // The whole range is checked before popping so a bad range leaves memory and stack untouched
+                    let range = self.memory_range(start, count)?;
+                    let value = self.pop_value(opcode)?;
+                    if range.end > self.memory.len() {
+                        self.memory.resize(range.end, 0);
+                    }
+                    if !range.is_empty() {
+                        self.note_write(range.end - 1);
+                    }
+                    self.memory[range].fill(value);
+                } else {
+                    return Err(VmError::MissingOperand { opcode, pc: self.pc });
+                }
+            },
+            Opcode::MCP => {
+                if let (Some(destination), Some(source)) = (operand_1, operand_2) {
+                    let length = self.peek_int(opcode)?.ok_or(VmError::StackUnderflow { opcode, pc: self.pc })?;
+                    let destination = self.memory_range(destination, length)?;
+                    let source = self.memory_range(source, length)?;
+                    self.stack.pop();
+                    // Nothing to copy, and a source past the end of the written memory isn't a valid copy_within range
+                    if length == 0 {
+                        return Ok(self.pc + 1);
+                    }
+                    // Unwritten source cells read as 0, so growing memory over both regions keeps the copy a plain copy_within,
+                    // which handles overlapping regions like memmove
+                    let end = destination.end.max(source.end);
+                    if end > self.memory.len() {
+                        self.memory.resize(end, 0);
+                    }
+                    self.note_write(destination.end - 1);
+                    self.memory.copy_within(source, destination.start);
+                } else {
+                    return Err(VmError::MissingOperand { opcode, pc: self.pc });
+                }
+            },
+            Opcode::TIM => {
+                // Truncated to 32 bits, so this wraps to negative values after 2038-01-19, TIMM doesn't
+                let seconds = epoch_time().as_secs();
+                self.push(Value::Int(seconds as i32))?;
+            },
+            Opcode::TIMM => {
+                let milliseconds = epoch_time().as_millis() as u64;
+                if self.stack.len() + 2 > self.config.max_stack {
+                    return Err(VmError::StackOverflow { limit: self.config.max_stack, pc: self.pc });
+                }
+                self.push(Value::Int((milliseconds >> 32) as i32))?;
+                self.push(Value::Int(milliseconds as i32))?;
+            },
+            Opcode::PCP => {
+                self.push(Value::Int(self.pc as i32))?;
+            },
+            Opcode::RND => {
+                let random = self.next_random();
+                let value = match (operand_1, operand_2) {
+                    (Some(low), Some(high)) => {
+                        if high <= low {
+                            return Err(VmError::InvalidRange { low, high, pc: self.pc });
+                        }
+                        let span = (high as i64 - low as i64) as u64;
+                        (low as i64 + (random % span) as i64) as i32
+                    },
+                    (None, None) => random as i32,
+                    _ => return Err(VmError::MissingOperand { opcode, pc: self.pc }),
+                };
+                self.push(Value::Int(value))?;
+            },
+            Opcode::MOV => {
+                if let (Some(operand_1), Some(operand_2)) = (operand_1, operand_2) {
+                    let source = self.register_index(operand_1)?;
+                    let destination = self.register_index(operand_2)?;
+                    let value = self.registers[source];
+
+                    self.registers[source] = 0;
+                    self.registers[destination] = value;
+                } else {
+                    return Err(VmError::MissingOperand { opcode, pc: self.pc });
+                }
+            }
+            Opcode::SWR => {
+                if let (Some(operand_1), Some(operand_2)) = (operand_1, operand_2) {
+                    let first = self.register_index(operand_1)?;
+                    let second = self.register_index(operand_2)?;
+                    self.registers.swap(first, second);
+                } else {
+                    return Err(VmError::MissingOperand { opcode, pc: self.pc });
+                }
+            },
+            Opcode::CLR => {
+                if let Some(reg) = operand_1 {
+                    let reg = self.register_index(reg)?;
+                    self.registers[reg] = 0;
+                } else {
+                    self.registers.fill(0);
+                }
+            },
+            Opcode::COP => {
+                if let (Some(operand_1), Some(operand_2)) = (operand_1, operand_2) {
+                    let source = self.register_index(operand_1)?;
+                    let destination = self.register_index(operand_2)?;
+
+                    self.registers[destination] = self.registers[source];
+                } else {
+                    return Err(VmError::MissingOperand { opcode, pc: self.pc });
+                }
+            }
+        }
+
+        Ok(self.pc + 1)
+    }
+
+    // Narrows an exact arithmetic result to an i32 according to the configured overflow mode
+    fn apply_overflow(&self, result: i64) -> Result<i32, VmError> {
+        match self.config.overflow {
+            OverflowMode::Wrapping => Ok(result as i32),
+            OverflowMode::Checked => i32::try_from(result).map_err(|_| VmError::ArithmeticOverflow { pc: self.pc }),
+            OverflowMode::Saturating => Ok(result.clamp(i32::MIN as i64, i32::MAX as i64) as i32),
+        }
+    }
+
+    // Advances the xorshift64 generator and returns its new state
+    fn next_random(&mut self) -> u64 {
+        let mut x = self.rng_state;
+        x ^= x << 13;
+        x ^= x >> 7;
+        x ^= x << 17;
+        self.rng_state = x;
+        x
+    }
+
+    // Pushes a value, failing once the stack holds max_stack values
+    fn push(&mut self, value: Value) -> Result<(), VmError> {
+        if self.stack.len() >= self.config.max_stack {
+            return Err(VmError::StackOverflow { limit: self.config.max_stack, pc: self.pc });
+        }
+        self.stack.push(value);
+        Ok(())
+    }
+
+    // Integer exponentiation (by squaring, through i32::pow and friends) following the overflow mode
+    fn power(&self, opcode: Opcode, base: i32, exponent: i32) -> Result<i32, VmError> {
+        let exponent = u32::try_from(exponent).map_err(|_| VmError::InvalidArgument { opcode, value: exponent, pc: self.pc })?;
+        match self.config.overflow {
+            OverflowMode::Wrapping => Ok(base.wrapping_pow(exponent)),
+            OverflowMode::Checked => base.checked_pow(exponent).ok_or(VmError::ArithmeticOverflow { pc: self.pc }),
+            OverflowMode::Saturating => Ok(base.saturating_pow(exponent)),
+        }
+    }
+
+    // Euclid's algorithm on the absolute values, so the result is never negative and gcd(0, 0) is 0.
+    // Only gcd(i32::MIN, 0) and gcd(i32::MIN, i32::MIN) don't fit in an i32, they follow the overflow mode
+    fn gcd(&self, a: i32, b: i32) -> Result<i32, VmError> {
+        let (mut a, mut b) = (a.unsigned_abs(), b.unsigned_abs());
+        while b != 0 {
+            (a, b) = (b, a % b);
+        }
+        self.apply_overflow(a as i64)
+    }
+
+    // The stack helpers below check the stack length (and value types) before removing anything,
+    // so an instruction that fails with StackUnderflow leaves the stack exactly as it was
+
+    // Pops the top value of the stack, whatever its type
+    fn pop_any(&mut self, opcode: Opcode) -> Result<Value, VmError> {
+        self.stack.pop().ok_or(VmError::StackUnderflow { opcode, pc: self.pc })
+    }
+
+    // Pops the top value of the stack, which must be an integer
+    fn pop_value(&mut self, opcode: Opcode) -> Result<i32, VmError> {
+        let value = self.peek_int(opcode)?.ok_or(VmError::StackUnderflow { opcode, pc: self.pc })?;
+        self.stack.pop();
+        Ok(value)
+    }
+
+    // Reads the top value of the stack without popping it, None if the stack is empty
+    fn peek_int(&self, opcode: Opcode) -> Result<Option<i32>, VmError> {
+        match self.stack.last() {
+            Some(value) => value.as_int().map(Some).ok_or(VmError::TypeMismatch { opcode, pc: self.pc }),
+            None => Ok(None),
+        }
+    }
+
+    // Reads the two latest values of the stack without popping them, as (second-to-top, top)
+    fn peek_pair(&self, opcode: Opcode) -> Result<(Value, Value), VmError> {
+        match self.stack.len() {
+            len if len >= 2 => Ok((self.stack[len - 2], self.stack[len - 1])),
+            _ => Err(VmError::StackUnderflow { opcode, pc: self.pc }),
+        }
+    }
+
+    // Pops the two latest values of the stack, returned in push order as (second-to-top, top).
+    // Both must be integers, on error the stack is left untouched
+    fn pop_pair(&mut self, opcode: Opcode) -> Result<(i32, i32), VmError> {
+        let (second, top) = self.peek_pair(opcode)?;
+        match (second.as_int(), top.as_int()) {
+            (Some(second), Some(top)) => {
+                self.stack.truncate(self.stack.len() - 2);
+                Ok((second, top))
+            },
+            _ => Err(VmError::TypeMismatch { opcode, pc: self.pc }),
+        }
+    }
+
+    // Like pop_pair, but both values must be floats
+    fn pop_float_pair(&mut self, opcode: Opcode) -> Result<(f32, f32), VmError> {
+        let (second, top) = self.peek_pair(opcode)?;
+        match (second.as_float(), top.as_float()) {
+            (Some(second), Some(top)) => {
+                self.stack.truncate(self.stack.len() - 2);
+                Ok((second, top))
+            },
+            _ => Err(VmError::TypeMismatch { opcode, pc: self.pc }),
+        }
+    }
+
+    // Reads the values of two registers, checking that both indices are valid
+    fn register_pair(&self, index_1: i32, index_2: i32) -> Result<(i32, i32), VmError> {
+        let value_1 = self.registers[self.register_index(index_1)?];
+        let value_2 = self.registers[self.register_index(index_2)?];
+        Ok((value_1, value_2))
+    }
+
+    fn register_index(&self, index: i32) -> Result<usize, VmError> {
+        if index >= 0 && (index as usize) < self.registers.len() {
+            Ok(index as usize)
+        } else {
+            Err(VmError::InvalidRegister { index, pc: self.pc })
+        }
+    }
+
+    // Shifting an i32 by a negative amount or by 32 or more bits is invalid
+    fn shift_amount(&self, amount: i32) -> Result<u32, VmError> {
+        if (0..32).contains(&amount) {
+            Ok(amount as u32)
+        } else {
+            Err(VmError::InvalidShift { amount, pc: self.pc })
+        }
+    }
+
+    fn memory_address(&self, address: i32) -> Result<usize, VmError> {
+        if address >= 0 && (address as usize) < self.config.max_memory {
+            Ok(address as usize)
+        } else {
+            Err(VmError::MemoryOutOfBounds { address, pc: self.pc })
+        }
+    }
+
+    // Bounds-checks the count cells starting at start, returning them as a range of addresses
+    fn memory_range(&self, start: i32, count: i32) -> Result<std::ops::Range<usize>, VmError> {
+        let first = self.memory_address(start)?;
+        if count < 0 {
+            return Err(VmError::MemoryOutOfBounds { address: start.saturating_add(count), pc: self.pc });
+        }
+        if count > 0 {
+            self.memory_address(start.saturating_add(count - 1))?;
+        }
+        Ok(first..first + count as usize)
+    }
+
+    // Stdin is locked per read rather than held, so the host can still use it between instructions
+    fn read_input_line(&mut self, line: &mut String) -> std::io::Result<usize> {
+        match &mut self.input {
+            Some(input) => input.read_line(line),
+            None => std::io::stdin().read_line(line),
+        }
+    }
+
+    // Reads an already bounds-checked address
+    fn read_memory(&self, address: usize) -> i32 {
+        self.memory.get(address).copied().unwrap_or(0)
+    }
+
+    // Writes to an already bounds-checked address, growing memory if needed
+    fn write_memory(&mut self, address: usize, value: i32) {
+        if address >= self.memory.len() {
+            self.memory.resize(address + 1, 0);
+        }
+        self.memory[address] = value;
+        self.note_write(address);
+    }
+
+    fn note_write(&mut self, address: usize) {
+        self.max_address_written = Some(self.max_address_written.map_or(address, |highest| highest.max(address)));
+    }
+
+    // Checks a jump target, the loader has already turned label operands into program addresses
+    fn jump_target(&self, target: i32) -> Result<usize, VmError> {
+        if target >= 0 && (target as usize) <= self.program.len() {
+            // Jumping just past the last instruction ends the program, like a label on the last line does
+            Ok(target as usize)
+        } else {
+            Err(VmError::InvalidJumpTarget { target, pc: self.pc })
+        }
+    }
+
+    fn debug_state(&mut self) -> Result<(), VmError> {
+        let memory: Vec<(usize, i32)> = self.memory.iter().copied().enumerate().filter(|&(_, value)| value != 0).collect();
+        let stack: Vec<String> = self.stack.iter().map(|value| value.to_string()).collect();
+        // Sorted by name so the output is the same on every run
+        let labels: BTreeMap<&String, &usize> = self.labels.iter().collect();
+        writeln!(self.output, "PC: {}, Stack: [{}], Memory: {:?}, Registers: {:?}, Labels: {:?}, Call Stack: {:?}", self.pc, stack.join(", "), memory, self.registers, labels, self.call_stack)?;
+        Ok(())
+    }
+}
+
+fn epoch_time() -> Duration {
+    SystemTime::now().duration_since(UNIX_EPOCH).expect("system clock is set before 1970")
+}
+
+// xorshift gets stuck at 0, so a zero seed falls back to the default one
+fn rng_seed(seed: u64) -> u64 {
+    if seed == 0 { DEFAULT_SEED } else { seed }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_util::*;
+
+    #[test]
+    fn comparisons_use_push_order() {
+        for (opcode, expected) in [(Opcode::GTH, 1), (Opcode::LTH, 0), (Opcode::GTE, 1), (Opcode::LTE, 0)] {
+            let (vm, result) = run_program(vec![(Opcode::PSH, Some(5), None), (Opcode::PSH, Some(3), None), (opcode, None, None)]);
+            result.unwrap();
+            assert_eq!(ints(&vm), [expected], "5 {:?} 3", opcode);
+        }
+        for opcode in [Opcode::GTE, Opcode::LTE] {
+            let (vm, _) = run_program(vec![(Opcode::PSH, Some(4), None), (Opcode::PSH, Some(4), None), (opcode, None, None)]);
+            assert_eq!(ints(&vm), [1], "4 {:?} 4", opcode);
+        }
+    }
+
+    #[test]
+    fn neq_pushes_whether_values_differ() {
+        let (vm, result, _) = run_source("PSH 4\nPSH 9\nNEQ");
+        result.unwrap();
+        assert_eq!(ints(&vm), [1]);
+        let (vm, _, _) = run_source("PSH 4\nPSH 4\nNEQ");
+        assert_eq!(ints(&vm), [0]);
+    }
+
+    #[test]
+    fn register_comparisons_read_register_values() {
+        let (vm, result, _) = run_source("PSH 7\nSET 0\nPSH 7\nSET 1\nPSH 2\nSET 2\nEQU 0 1\nEQU 0 2\nNEQ 0 2\nGTH 0 2\nLTH 0 2\nGTE 0 1\nLTE 2 0");
+        result.unwrap();
+        assert_eq!(ints(&vm), [1, 0, 1, 1, 0, 1, 1]);
+    }
+
+    #[test]
+    fn out_of_range_registers_are_errors() {
+        for source in ["PSH 1\nSET 20", "GET 20", "GET -1", "INC 20", "DEC 20", "MOV 0 20", "COP 20 0"] {
+            let (_, result, _) = run_source(source);
+            assert!(matches!(result, Err(VmError::InvalidRegister { .. })), "{}: {:?}", source, result);
+        }
+    }
+
+    #[test]
+    fn bitwise_and_or_xor() {
+        let (vm, result, _) = run_source("PSH 6\nPSH 3\nAND\nPSH 6\nPSH 1\nOR\nPSH 6\nPSH 3\nXOR");
+        result.unwrap();
+        assert_eq!(ints(&vm), [2, 7, 5]);
+        let (vm, _, _) = run_source("PSH 6\nSET 0\nPSH 3\nSET 1\nAND 0 1\nOR 0 1\nXOR 0 1");
+        assert_eq!(ints(&vm), [2, 7, 5]);
+    }
+
+    #[test]
+    fn shifts() {
+        let (vm, result, _) = run_source("PSH 1\nPSH 4\nSHL\nPSH -16\nPSH 2\nSHR");
+        result.unwrap();
+        assert_eq!(ints(&vm), [16, -4]);
+    }
+
+    #[test]
+    fn shift_by_40_is_an_error() {
+        let (_, result, _) = run_source("PSH 1\nPSH 40\nSHL");
+        assert!(matches!(result, Err(VmError::InvalidShift { amount: 40, pc: 2 })), "{:?}", result);
+    }
+
+    #[test]
+    fn not_complements() {
+        let (vm, result, _) = run_source("PSH 0\nNOT\nPSH 5\nSET 3\nNOT 3\nGET 3");
+        result.unwrap();
+        assert_eq!(ints(&vm), [-1, -6]);
+    }
+
+    #[test]
+    fn configured_register_count() {
+        let mut vm = VM::with_config(VmConfig { registers: 16, ..VmConfig::default() }).unwrap();
+        vm.load_program(vec![(Opcode::PSH, Some(9), None), (Opcode::SET, Some(12), None), (Opcode::GET, Some(12), None), (Opcode::GET, Some(16), None)]);
+        let result = vm.run();
+        assert_eq!(vm.registers().len(), 16);
+        assert_eq!(vm.registers()[12], 9);
+        assert_eq!(ints(&vm), [9]);
+        assert!(matches!(result, Err(VmError::InvalidRegister { index: 16, pc: 3 })), "{:?}", result);
+        assert!(matches!(VM::with_config(VmConfig { registers: 0, ..VmConfig::default() }), Err(VmError::InvalidConfig { .. })));
+    }
+
+    #[test]
+    fn unwritten_memory_reads_as_zero() {
+        let (vm, result, _) = run_source("LOA 500\nPSH 7\nSTR 1000\nLOA 1000\nLOA 999");
+        result.unwrap();
+        assert_eq!(ints(&vm), [0, 7, 0]);
+    }
+
+    // Times a tight store/load loop, run with `cargo test --release -- --ignored --nocapture store_load_benchmark`
+    #[test]
+    #[ignore]
+    fn store_load_benchmark() {
+        const ITERATIONS: i32 = 1_000_000;
+        let source = format!("PSH {}\nloop:\nSTR 4096\nLOA 4096\nSTR 8\nLOA 8\nDEC\nJNZ loop\nHLT", ITERATIONS);
+        let (mut vm, _) = load_source(&source).unwrap();
+        let start = Instant::now();
+        vm.run().unwrap();
+        let elapsed = start.elapsed();
+        println!("{} store/load iterations in {:?} ({:?} per instruction)", ITERATIONS, elapsed, elapsed / vm.instructions_executed() as u32);
+    }
+
+    #[test]
+    fn instruction_limit_stops_a_self_loop() {
+        let (mut vm, _) = load_source("loop:\nNOP\nJMP loop").unwrap();
+        let result = vm.run_with_limit(1000);
+        assert!(matches!(result, Err(VmError::InstructionLimitExceeded { limit: 1000, pc: 0 })), "{:?}", result);
+        assert_eq!(vm.instructions_executed(), 1000);
+        assert_eq!(vm.halt_reason(), Some(HaltReason::LimitExceeded));
+    }
+
+    #[test]
+    fn instruction_limit_counts_calls_and_returns() {
+        let (mut vm, _) = load_source("CALL f\nHLT\nf:\nRET").unwrap();
+        assert!(vm.run_with_limit(3).is_ok());
+        vm.reset();
+        assert!(matches!(vm.run_with_limit(2), Err(VmError::InstructionLimitExceeded { limit: 2, pc: 1 })));
+    }
+
+    #[test]
+    fn breakpoint_inside_a_loop() {
+        let (mut vm, _) = load_source("PSH 3\nloop:\nDEC\nJNZ loop\nHLT").unwrap();
+        assert!(vm.add_breakpoint_label("loop"));
+        assert!(!vm.add_breakpoint_label("missing"));
+        assert_eq!(vm.run_until_breakpoint().unwrap(), Some(1));
+        assert_eq!(ints(&vm), [3]);
+        assert_eq!(vm.run_until_breakpoint().unwrap(), Some(1));
+        assert_eq!(ints(&vm), [2]);
+        assert!(vm.remove_breakpoint(1));
+        assert_eq!(vm.run_until_breakpoint().unwrap(), None);
+        assert_eq!(ints(&vm), [0]);
+        assert_eq!(vm.halt_reason(), Some(HaltReason::Halted));
+    }
+
+    #[test]
+    fn breakpoint_on_the_first_instruction() {
+        let (mut vm, _) = load_source("PSH 1\nPSH 2\nHLT").unwrap();
+        vm.add_breakpoint(0);
+        assert_eq!(vm.run_until_breakpoint().unwrap(), Some(0));
+        assert!(vm.stack().is_empty());
+        assert_eq!(vm.run_until_breakpoint().unwrap(), None);
+        assert_eq!(ints(&vm), [1, 2]);
+        vm.reset();
+        assert_eq!(vm.run_until_breakpoint().unwrap(), Some(0));
+        assert!(vm.stack().is_empty());
+        // Stepping off the breakpoint by hand doesn't make the next call skip it when the program comes back
+        let (mut vm, _) = load_source("loop:\nPSH 1\nJMP loop").unwrap();
+        vm.add_breakpoint(0);
+        assert_eq!(vm.run_until_breakpoint().unwrap(), Some(0));
+        vm.step().unwrap();
+        vm.step().unwrap();
+        assert_eq!(vm.run_until_breakpoint().unwrap(), Some(0));
+        assert_eq!(ints(&vm), [1]);
+    }
+
+    #[test]
+    fn restore_rolls_back_to_a_snapshot() {
+        let (mut vm, _) = load_source("PSH 1\nSTR 0\nPSH 2\nSET 3\nCALL f\nHLT\nf:\nPSH 3\nSTR 0\nRET").unwrap();
+        for _ in 0..5 {
+            vm.step().unwrap();
+        }
+        let snapshot = vm.snapshot();
+        vm.step().unwrap();
+        vm.step().unwrap();
+        assert_ne!(vm.snapshot(), snapshot);
+        vm.restore(&snapshot);
+        assert_eq!(vm.snapshot(), snapshot);
+        assert_eq!(vm.pc(), 6);
+        vm.run().unwrap();
+        assert_eq!(vm.registers()[3], 2);
+        assert!(vm.stack().is_empty());
+        assert_eq!(vm.exit_code(), Some(0));
+    }
+
+    #[test]
+    fn jmp_to_a_label_defined_later() {
+        let (vm, result, _) = run_source("PSH 1\nJMP skip\nPSH 2\nskip:\nPSH 3");
+        result.unwrap();
+        assert_eq!(ints(&vm), [1, 3]);
+    }
+
+    #[test]
+    fn relative_jumps() {
+        let (vm, result, _) = run_source("PSH 1\nJMR 3\nPSH 2\nPSH 3\nPSH 4");
+        result.unwrap();
+        assert_eq!(ints(&vm), [1, 4]);
+        let (vm, result, _) = run_source("PSH 3\nDEC\nDUP\nJEZP 5\nJMR -3\nHLT");
+        result.unwrap();
+        assert_eq!(ints(&vm), [0]);
+        assert_eq!(vm.instructions_executed(), 13);
+        let (_, result, _) = run_source("JMR -2");
+        assert!(matches!(result, Err(VmError::InvalidJumpTarget { target: -2, pc: 0 })), "{:?}", result);
+        // Like JMP, jumping just past the last instruction ends the program
+        let (vm, result, _) = run_source("PSH 1\nJMR 2\nPSH 2");
+        result.unwrap();
+        assert_eq!(ints(&vm), [1]);
+        let (_, result, _) = run_source("PSH 1\nJMR 3\nPSH 2");
+        assert!(matches!(result, Err(VmError::InvalidJumpTarget { target: 4, pc: 1 })), "{:?}", result);
+    }
+
+    #[test]
+    fn indirect_jump_dispatches_on_a_stack_value() {
+        let (vm, result, _) = run_source("PSH 5\nPSH 7\nSWP\nPOP\nJMI\nPSH 100\nHLT\nPSH 200\nHLT");
+        result.unwrap();
+        assert_eq!(ints(&vm), [200]);
+        let (_, result, _) = run_source("JMI");
+        assert!(matches!(result, Err(VmError::StackUnderflow { opcode: Opcode::JMI, pc: 0 })), "{:?}", result);
+        let (_, result, _) = run_source("PSH 9\nJMI");
+        assert!(matches!(result, Err(VmError::InvalidJumpTarget { target: 9, pc: 1 })), "{:?}", result);
+        // Like JMP, jumping just past the last instruction ends the program
+        let (vm, result, _) = run_source("PSH 1\nPSH 4\nJMI\nPSH 2");
+        result.unwrap();
+        assert_eq!(ints(&vm), [1]);
+        let (_, result, _) = run_source("PSH 3\nJMI\nPSH 2");
+        result.unwrap();
+        let (_, result, _) = run_source("PSH 4\nJMI\nPSH 2");
+        assert!(matches!(result, Err(VmError::InvalidJumpTarget { target: 4, pc: 1 })), "{:?}", result);
+    }
+
+    #[test]
+    fn prs_prints_until_a_zero_cell() {
+        let (_, result, output) = run_source("PSH \"Hi\\0\"\nSTR 12\nSTR 11\nSTR 10\nPSH 33\nSTR 13\nPRS 10");
+        result.unwrap();
+        assert_eq!(output, "Hi");
+    }
+
+    #[test]
+    fn overflow_modes() {
+        let program = vec![(Opcode::PSH, Some(i32::MAX), None), (Opcode::PSH, Some(1), None), (Opcode::ADD, None, None)];
+        let run = |overflow| {
+            let mut vm = VM::with_config(VmConfig { overflow, ..VmConfig::default() }).unwrap();
+            vm.load_program(program.clone());
+            let result = vm.run();
+            (vm, result)
+        };
+
+        let (vm, result) = run(OverflowMode::Wrapping);
+        result.unwrap();
+        assert_eq!(ints(&vm), [i32::MIN]);
+        let (_, result) = run(OverflowMode::Checked);
+        assert!(matches!(result, Err(VmError::ArithmeticOverflow { pc: 2 })), "{:?}", result);
+        let (vm, result) = run(OverflowMode::Saturating);
+        result.unwrap();
+        assert_eq!(ints(&vm), [i32::MAX]);
+    }
+
+    #[test]
+    fn reset_reruns_the_program() {
+        let (mut vm, output) = load_source("PSH 2\nSTR 0\nLOA 0\nSET 1\nCALL print\nHLT\nprint:\nGET 1\nPPT\nRET").unwrap();
+        vm.run().unwrap();
+        let first = vm.snapshot();
+        vm.reset();
+        assert_eq!(vm.pc(), 0);
+        assert!(vm.stack().is_empty());
+        assert!(vm.registers().iter().all(|&register| register == 0));
+        vm.run().unwrap();
+        assert_eq!(vm.snapshot(), first);
+        assert_eq!(output.contents(), "2\n2\n");
+    }
+
+    #[test]
+    fn output_goes_to_the_configured_writer() {
+        let output = SharedOutput::default();
+        let mut vm = VM::new().with_output(output.clone());
+        vm.load_program(vec![(Opcode::PSH, Some(65), None), (Opcode::PRC, None, None)]);
+        vm.run().unwrap();
+        assert_eq!(output.contents(), "A");
+    }
+
+    #[test]
+    fn input_comes_from_the_configured_reader() {
+        let mut vm = VM::new().with_input(std::io::Cursor::new("42\nnope\n"));
+        vm.load_program(vec![(Opcode::INP, None, None), (Opcode::INP, None, None)]);
+        let result = vm.run();
+        assert_eq!(ints(&vm), [42]);
+        assert!(matches!(result, Err(VmError::InvalidInput { ref input, pc: 1 }) if input == "nope"), "{:?}", result);
+    }
+
+    #[test]
+    fn float_arithmetic() {
+        let (vm, result, _) = run_source("PSHF 3.5\nPSHF 1.5\nFADD\nPSHF 1.5\nPOP");
+        result.unwrap();
+        assert_eq!(vm.stack(), [Value::Float(5.0)]);
+        let (vm, result, _) = run_source("PSHF 1\nPSHF 0\nFDIV\nPSHF 7.9\nFTOI\nPSH 2\nITOF");
+        result.unwrap();
+        assert_eq!(vm.stack(), [Value::Float(f32::INFINITY), Value::Int(7), Value::Float(2.0)]);
+    }
+
+    #[test]
+    fn mixing_ints_and_floats_is_an_error() {
+        let (vm, result, _) = run_source("PSH 3\nPSHF 1.5\nADD");
+        assert!(matches!(result, Err(VmError::TypeMismatch { opcode: Opcode::ADD, pc: 2 })), "{:?}", result);
+        assert_eq!(vm.stack(), [Value::Int(3), Value::Float(1.5)]);
+        let (_, result, _) = run_source("PSHF 3.5\nPSH 1\nFADD");
+        assert!(matches!(result, Err(VmError::TypeMismatch { opcode: Opcode::FADD, pc: 2 })), "{:?}", result);
+    }
+
+    #[test]
+    fn stack_addressed_memory() {
+        let (vm, result, _) = run_source("PSH 42\nPSH 10\nPSH 5\nADD\nSTRI\nPSH 3\nPSH 5\nMUL\nLOAI");
+        result.unwrap();
+        assert_eq!(ints(&vm), [42]);
+        let (vm, result, _) = run_source("PSH 1\nPSH -1\nSTRI");
+        assert!(matches!(result, Err(VmError::MemoryOutOfBounds { address: -1, pc: 2 })), "{:?}", result);
+        assert_eq!(ints(&vm), [1, -1]);
+    }
+
+    #[test]
+    fn register_addressed_memory() {
+        let (vm, result, _) = run_source("PSH 100\nSET 2\nPSH 7\nSTRR 2\nINC 2\nPSH 8\nSTRR 2\nLOA 100\nLOA 101\nLOAR 2");
+        result.unwrap();
+        assert_eq!(ints(&vm), [7, 8, 8]);
+        let (_, result, _) = run_source("LOAR 9");
+        assert!(matches!(result, Err(VmError::InvalidRegister { index: 9, pc: 0 })), "{:?}", result);
+    }
+
+    #[test]
+    fn str_without_an_address_keeps_the_stack() {
+        let (vm, result) = run_program(vec![(Opcode::PSH, Some(5), None), (Opcode::STR, None, None)]);
+        assert!(matches!(result, Err(VmError::MissingOperand { opcode: Opcode::STR, pc: 1 })), "{:?}", result);
+        assert_eq!(ints(&vm), [5]);
+    }
+
+    #[test]
+    fn mov_and_cop_need_both_registers() {
+        for opcode in [Opcode::MOV, Opcode::COP] {
+            let (vm, result) = run_program(vec![(Opcode::PSH, Some(3), None), (Opcode::SET, Some(0), None), (opcode, Some(5), None)]);
+            assert!(matches!(result, Err(VmError::MissingOperand { opcode: failed, pc: 2 }) if failed == opcode), "{:?}", result);
+            assert_eq!(vm.registers()[5], 0);
+            assert_eq!(vm.registers()[0], 3);
+        }
+        assert!(matches!(load_source("MOV 5"), Err(VmError::ArityMismatch { opcode: Opcode::MOV, line: 1 })));
+    }
+
+    #[test]
+    fn profiling_counts_executed_opcodes() {
+        let (mut vm, _) = load_source("PSH 0\nPSH 10\nSET 0\nloop:\nPSH 2\nADD\nDEC 0\nGET 0\nJNZP loop").unwrap();
+        vm.enable_profiling();
+        vm.run().unwrap();
+        let report = vm.profile_report();
+        assert_eq!(report[..5], [(Opcode::PSH, 12), (Opcode::ADD, 10), (Opcode::DEC, 10), (Opcode::GET, 10), (Opcode::JNZP, 10)]);
+        assert_eq!(ints(&vm), [20]);
+    }
+
+    #[test]
+    fn trace_hook_sees_every_executed_instruction() {
+        let (mut vm, _) = load_source("PSH 0\nJEZ zero\nPSH 1\nzero:\nPSH 2\nHLT").unwrap();
+        let trace = std::rc::Rc::new(std::cell::RefCell::new(Vec::new()));
+        let recorder = trace.clone();
+        vm.set_trace_hook(Box::new(move |pc, opcode, stack| recorder.borrow_mut().push((pc, opcode, stack.len()))));
+        vm.run().unwrap();
+        assert_eq!(*trace.borrow(), [(0, Opcode::PSH, 0), (1, Opcode::JEZ, 1), (3, Opcode::PSH, 1), (4, Opcode::HLT, 2)]);
+    }
+
+    #[test]
+    fn logical_and_or() {
+        let (vm, result, _) = run_source("PSH 5\nPSH 0\nANL\nPSH 5\nPSH 0\nORL\nPSH 5\nPSH -2\nANL\nPSH 0\nPSH 0\nORL");
+        result.unwrap();
+        assert_eq!(ints(&vm), [0, 1, 1, 0]);
+        let (vm, _, _) = run_source("PSH 5\nSET 0\nANL 0 1\nORL 0 1");
+        assert_eq!(ints(&vm), [0, 1]);
+    }
+
+    #[test]
+    fn neg_flips_the_sign() {
+        let (vm, result, _) = run_source("PSH 7\nNEG\nPSH -3\nSET 1\nNEG 1\nGET 1\nPSH -2147483648\nNEG");
+        result.unwrap();
+        assert_eq!(ints(&vm), [-7, 3, i32::MIN]);
+        let checked = VmConfig { overflow: OverflowMode::Checked, ..VmConfig::default() };
+        let (_, result, _) = run_source_with(checked, "PSH -2147483648\nNEG");
+        assert!(matches!(result, Err(VmError::ArithmeticOverflow { pc: 1 })), "{:?}", result);
+    }
+
+    #[test]
+    fn abs_of_positive_negative_and_minimum() {
+        let (vm, result, _) = run_source("PSH 4\nABS\nPSH -4\nABS\nPSH -9\nSET 2\nABS 2\nGET 2\nPSH -2147483648\nABS");
+        result.unwrap();
+        assert_eq!(ints(&vm), [4, 4, 9, i32::MIN]);
+        let saturating = VmConfig { overflow: OverflowMode::Saturating, ..VmConfig::default() };
+        let (vm, _, _) = run_source_with(saturating, "PSH -2147483648\nABS");
+        assert_eq!(ints(&vm), [i32::MAX]);
+        let checked = VmConfig { overflow: OverflowMode::Checked, ..VmConfig::default() };
+        let (_, result, _) = run_source_with(checked, "PSH -2147483648\nABS");
+        assert!(matches!(result, Err(VmError::ArithmeticOverflow { pc: 1 })), "{:?}", result);
+    }
+
+    #[test]
+    fn min_and_max() {
+        let (vm, result, _) = run_source("PSH 3\nPSH 7\nMIN\nPSH 3\nPSH 7\nMAX\nPSH -3\nPSH 2\nMIN\nPSH -3\nPSH -7\nMAX");
+        result.unwrap();
+        assert_eq!(ints(&vm), [3, 7, -3, -3]);
+        let (vm, _, _) = run_source("PSH 3\nSET 0\nPSH -7\nSET 1\nMIN 0 1\nMAX 0 1");
+        assert_eq!(ints(&vm), [-7, 3]);
+    }
+
+    #[test]
+    fn push_loop_hits_the_stack_limit() {
+        let config = VmConfig { max_stack: 4, ..VmConfig::default() };
+        let (vm, result, _) = run_source_with(config, "loop:\nPSH 1\nJMP loop");
+        assert!(matches!(result, Err(VmError::StackOverflow { limit: 4, pc: 0 })), "{:?}", result);
+        assert_eq!(vm.stack().len(), 4);
+    }
+
+    #[test]
+    fn pek_copies_by_depth() {
+        let (vm, result, _) = run_source("PSH 10\nPSH 20\nPSH 30\nPEK 2\nPEK 0");
+        result.unwrap();
+        assert_eq!(ints(&vm), [10, 20, 30, 10, 10]);
+        let (vm, result, _) = run_source("PSH 10\nPEK 1");
+        assert!(matches!(result, Err(VmError::StackUnderflow { opcode: Opcode::PEK, pc: 1 })), "{:?}", result);
+        assert_eq!(ints(&vm), [10]);
+    }
+
+    #[test]
+    fn rot_moves_the_third_value_to_the_top() {
+        let (vm, result, _) = run_source("PSH 0\nPSH 1\nPSH 2\nPSH 3\nROT");
+        result.unwrap();
+        assert_eq!(ints(&vm), [0, 2, 3, 1]);
+        let (vm, result, _) = run_source("PSH 1\nPSH 2\nROT");
+        assert!(matches!(result, Err(VmError::StackUnderflow { opcode: Opcode::ROT, pc: 2 })), "{:?}", result);
+        assert_eq!(ints(&vm), [1, 2]);
+    }
+
+    #[test]
+    fn over_copies_the_second_value() {
+        let (vm, result, _) = run_source("PSH 1\nPSH 2\nOVER");
+        result.unwrap();
+        assert_eq!(ints(&vm), [1, 2, 1]);
+        let (vm, result, _) = run_source("PSH 1\nOVER");
+        assert!(matches!(result, Err(VmError::StackUnderflow { opcode: Opcode::OVER, pc: 1 })), "{:?}", result);
+        assert_eq!(ints(&vm), [1]);
+    }
+
+    #[test]
+    fn drp_pops_several_values() {
+        let (vm, result, _) = run_source("PSH 1\nPSH 2\nPSH 3\nPSH 4\nPSH 5\nDRP 3");
+        result.unwrap();
+        assert_eq!(ints(&vm), [1, 2]);
+        let (vm, result, _) = run_source("PSH 1\nPSH 2\nDRP 3");
+        assert!(matches!(result, Err(VmError::StackUnderflow { opcode: Opcode::DRP, pc: 2 })), "{:?}", result);
+        assert_eq!(ints(&vm), [1, 2]);
+    }
+
+    #[test]
+    fn dmp_prints_a_memory_range() {
+        let (_, result, output) = run_source("PSH 7\nSTR 10\nPSH 9\nSTR 12\nDMP 10 3");
+        result.unwrap();
+        assert_eq!(output, "Memory[10..13]: [7, 0, 9]\n");
+        let (_, result, output) = run_source("DMP 1048575 2");
+        assert!(matches!(result, Err(VmError::MemoryOutOfBounds { .. })), "{:?}", result);
+        assert_eq!(output, "");
+    }
+
+    #[test]
+    fn equal_seeds_give_equal_random_sequences() {
+        let source = "RND\nRND\nRND -5 5\nRND 0 2\nRND 100 101";
+        let seeded = |seed| run_source_with(VmConfig { seed, ..VmConfig::default() }, source).0;
+        assert_eq!(ints(&seeded(7)), ints(&seeded(7)));
+        assert_ne!(ints(&seeded(7)), ints(&seeded(8)));
+        let values = ints(&seeded(7));
+        assert!((-5..5).contains(&values[2]) && (0..2).contains(&values[3]) && values[4] == 100, "{:?}", values);
+        let (_, result, _) = run_source("RND 5 5");
+        assert!(matches!(result, Err(VmError::InvalidRange { low: 5, high: 5, pc: 0 })), "{:?}", result);
+    }
+
+    #[test]
+    fn pcp_pushes_its_own_index() {
+        let (vm, result, _) = run_source("NOP\nPCP\nNOP\nPCP");
+        result.unwrap();
+        assert_eq!(ints(&vm), [1, 3]);
+    }
+
+    #[test]
+    fn len_counts_before_pushing() {
+        let (vm, result, _) = run_source("LEN\nPOP\nPSH 4\nPSH 5\nPSH 6\nLEN");
+        result.unwrap();
+        assert_eq!(ints(&vm), [4, 5, 6, 3]);
+    }
+
+    #[test]
+    fn inps_pushes_a_line_first_character_on_top() {
+        let mut vm = VM::new().with_input(std::io::Cursor::new("hi\r\n\n")).with_output(std::io::sink());
+        vm.load_program(vec![(Opcode::INPS, None, None), (Opcode::INPS, None, None)]);
+        vm.run().unwrap();
+        assert_eq!(ints(&vm), [0, 'i' as i32, 'h' as i32, 0]);
+    }
+
+    #[test]
+    fn dpn_duplicates_the_top_values_in_order() {
+        let (vm, result, _) = run_source("PSH 1\nPSH 2\nPSH 3\nDPN 2");
+        result.unwrap();
+        assert_eq!(ints(&vm), [1, 2, 3, 2, 3]);
+        let (vm, result, _) = run_source("PSH 1\nPSH 2\nDPN 3");
+        assert!(matches!(result, Err(VmError::StackUnderflow { opcode: Opcode::DPN, pc: 2 })), "{:?}", result);
+        assert_eq!(ints(&vm), [1, 2]);
+    }
+
+    #[test]
+    fn ext_halts_with_an_exit_code() {
+        let (vm, result, _) = run_source("PSH 7\nPSH 3\nEXT\nPSH 4");
+        result.unwrap();
+        assert_eq!(vm.exit_code(), Some(3));
+        assert_eq!(vm.halt_reason(), Some(HaltReason::Exited));
+        assert_eq!(ints(&vm), [7]);
+        let (vm, _, _) = run_source("HLT");
+        assert_eq!(vm.exit_code(), Some(0));
+        let (vm, _, _) = run_source("NOP");
+        assert_eq!(vm.exit_code(), None);
+    }
+
+    #[test]
+    fn minimum_divided_by_minus_one() {
+        let source = "PSH -2147483648\nPSH -1\nDIV\nPSH -2147483648\nPSH -1\nMOD\nPSH -2147483648\nSET 0\nPSH -1\nSET 1\nDIV 0 1\nMOD 0 1";
+        let (vm, result, _) = run_source(source);
+        result.unwrap();
+        assert_eq!(ints(&vm), [i32::MIN, 0, i32::MIN, 0]);
+        let saturating = VmConfig { overflow: OverflowMode::Saturating, ..VmConfig::default() };
+        let (vm, result, _) = run_source_with(saturating, source);
+        result.unwrap();
+        assert_eq!(ints(&vm), [i32::MAX, 0, i32::MAX, 0]);
+        let checked = VmConfig { overflow: OverflowMode::Checked, ..VmConfig::default() };
+        let (_, result, _) = run_source_with(checked, source);
+        assert!(matches!(result, Err(VmError::ArithmeticOverflow { pc: 2 })), "{:?}", result);
+    }
+
+    #[test]
+    fn div_and_mod_use_push_order() {
+        let (vm, result, _) = run_source("PSH 10\nPSH 3\nDIV\nPSH 10\nPSH 3\nMOD\nPSH 10\nSET 0\nPSH 3\nSET 1\nDIV 0 1\nMOD 0 1");
+        result.unwrap();
+        assert_eq!(ints(&vm), [3, 1, 3, 1]);
+        let (_, result, _) = run_source("PSH 10\nPSH 0\nDIV");
+        assert!(matches!(result, Err(VmError::DivByZero { pc: 2 })), "{:?}", result);
+    }
+
+    #[test]
+    fn alc_hands_out_separate_regions() {
+        let (vm, result, _) = run_source("ALC 4\nALC 3\nFRE\nALC 1");
+        result.unwrap();
+        assert_eq!(ints(&vm), [0, 4, 0]);
+        let config = VmConfig { max_memory: 10, ..VmConfig::default() };
+        let (vm, result, _) = run_source_with(config, "ALC 6\nALC 5");
+        assert!(matches!(result, Err(VmError::AllocationFailed { size: 5, pc: 1 })), "{:?}", result);
+        assert_eq!(ints(&vm), [0]);
+    }
+
+    #[test]
+    fn alc_clears_a_reused_region() {
+        let (vm, result, _) = run_source("ALC 2\nPSH 9\nSTR 1\nFRE\nALC 2\nLOA 1");
+        result.unwrap();
+        assert_eq!(ints(&vm), [0, 0, 0]);
+    }
+
+    #[test]
+    fn swr_swaps_two_registers() {
+        let (vm, result, _) = run_source("PSH 3\nSET 1\nPSH 9\nSET 4\nSWR 1 4");
+        result.unwrap();
+        assert_eq!(vm.registers()[1], 9);
+        assert_eq!(vm.registers()[4], 3);
+        let (vm, result, _) = run_source("PSH 3\nSET 1\nSWR 1 8");
+        assert!(matches!(result, Err(VmError::InvalidRegister { index: 8, pc: 2 })), "{:?}", result);
+        assert_eq!(vm.registers()[1], 3);
+    }
+
+    #[test]
+    fn clr_zeroes_one_or_all_registers() {
+        let (vm, result, _) = run_source("PSH 3\nSET 1\nPSH 9\nSET 4\nCLR 1");
+        result.unwrap();
+        assert_eq!(vm.registers(), [0, 0, 0, 0, 9, 0, 0, 0]);
+        let (vm, result, _) = run_source("PSH 3\nSET 1\nPSH 9\nSET 4\nCLR");
+        result.unwrap();
+        assert_eq!(vm.registers(), [0; 8]);
+        let (_, result, _) = run_source("CLR -1");
+        assert!(matches!(result, Err(VmError::InvalidRegister { index: -1, pc: 0 })), "{:?}", result);
+    }
+
+    #[test]
+    fn inc_and_dec_errors() {
+        for source in ["INC 99", "DEC 99", "INC -1"] {
+            let (vm, result, _) = run_source(source);
+            assert!(matches!(result, Err(VmError::InvalidRegister { .. })), "{}: {:?}", source, result);
+            assert_eq!(vm.registers(), [0; 8]);
+        }
+        for (source, opcode) in [("INC", Opcode::INC), ("DEC", Opcode::DEC)] {
+            let (vm, result, _) = run_source(source);
+            assert!(matches!(result, Err(VmError::StackUnderflow { opcode: failed, pc: 0 }) if failed == opcode), "{:?}", result);
+            assert!(vm.stack().is_empty());
+        }
+    }
+
+    #[test]
+    fn popping_jumps_leave_the_stack_clean() {
+        let (vm, result, _) = run_source("PSH 3\nSET 0\nloop:\nDEC 0\nGET 0\nJNZP loop");
+        result.unwrap();
+        assert!(vm.stack().is_empty());
+        let (vm, result, _) = run_source("PSH 3\nSET 0\nloop:\nDEC 0\nGET 0\nJNZ loop");
+        result.unwrap();
+        assert_eq!(ints(&vm), [2, 1, 0]);
+        let (vm, _, _) = run_source("PSH 0\nJEZP zero\nPSH 1\nzero:\nPSH -1\nJLZP negative\nPSH 2\nnegative:\nPSH 5\nJGZP positive\nPSH 3\npositive:");
+        assert!(vm.stack().is_empty());
+        let (_, result, _) = run_source("JEZP 0");
+        assert!(matches!(result, Err(VmError::StackUnderflow { opcode: Opcode::JEZP, pc: 0 })), "{:?}", result);
+    }
+
+    #[test]
+    fn pow_raises_to_a_power() {
+        let (vm, result, _) = run_source("PSH 2\nPSH 10\nPOW\nPSH -3\nPSH 3\nPOW\nPSH 7\nPSH 0\nPOW\nPSH 2\nSET 0\nPSH 5\nSET 1\nPOW 0 1");
+        result.unwrap();
+        assert_eq!(ints(&vm), [1024, -27, 1, 32]);
+        let (_, result, _) = run_source("PSH 2\nPSH -1\nPOW");
+        assert!(matches!(result, Err(VmError::InvalidArgument { opcode: Opcode::POW, value: -1, pc: 2 })), "{:?}", result);
+        let checked = VmConfig { overflow: OverflowMode::Checked, ..VmConfig::default() };
+        let (_, result, _) = run_source_with(checked, "PSH 2\nPSH 31\nPOW");
+        assert!(matches!(result, Err(VmError::ArithmeticOverflow { pc: 2 })), "{:?}", result);
+    }
+
+    #[test]
+    fn sqr_takes_the_integer_square_root() {
+        let (vm, result, _) = run_source("PSH 0\nSQR\nPSH 49\nSQR\nPSH 10\nSQR\nPSH 2147483647\nSQR\nPSH 15\nSET 2\nSQR 2\nGET 2");
+        result.unwrap();
+        assert_eq!(ints(&vm), [0, 7, 3, 46340, 3]);
+        let (_, result, _) = run_source("PSH -4\nSQR");
+        assert!(matches!(result, Err(VmError::InvalidArgument { opcode: Opcode::SQR, value: -4, pc: 1 })), "{:?}", result);
+    }
+
+    #[test]
+    fn gcd_of_two_values() {
+        let (vm, result, _) = run_source("PSH 48\nPSH 36\nGCD\nPSH -48\nPSH 18\nGCD\nPSH 0\nPSH 5\nGCD\nPSH 0\nPSH 0\nGCD\nPSH -2147483648\nPSH 0\nGCD");
+        result.unwrap();
+        assert_eq!(ints(&vm), [12, 6, 5, 0, i32::MIN]);
+        let (vm, _, _) = run_source("PSH 48\nSET 0\nPSH 36\nSET 1\nGCD 0 1");
+        assert_eq!(ints(&vm), [12]);
+    }
+
+    #[test]
+    fn labels_resolve_to_their_positions() {
+        let (vm, _) = load_source("PSH 1\nstart:\nDEC\nLBL marker\nHLT\nend:").unwrap();
+        assert_eq!(vm.resolve_label("start"), Some(1));
+        assert_eq!(vm.resolve_label("marker"), Some(2));
+        assert_eq!(vm.resolve_label("end"), Some(4));
+        assert_eq!(vm.resolve_label("missing"), None);
+        assert_eq!(vm.labels().len(), 3);
+    }
+
+    #[test]
+    fn label_for_pc_finds_the_closest_preceding_label() {
+        let (vm, _) = load_source("PSH 1\nmain:\nCALL square\nHLT\nsquare:\nhelper:\nDUP\nMUL\nRET").unwrap();
+        assert_eq!(vm.label_for_pc(0), None);
+        assert_eq!(vm.label_for_pc(1), Some("main"));
+        assert_eq!(vm.label_for_pc(2), Some("main"));
+        assert_eq!(vm.label_for_pc(4), Some("helper"));
+        assert_eq!(vm.label_for_pc(5), Some("helper"));
+    }
+
+    #[test]
+    fn prt_on_an_empty_stack_is_an_error() {
+        let (_, result, output) = run_source("PRT");
+        assert!(matches!(result, Err(VmError::StackUnderflow { opcode: Opcode::PRT, pc: 0 })), "{:?}", result);
+        assert_eq!(output, "");
+        let (vm, result, output) = run_source("PSH 4\nPRT");
+        result.unwrap();
+        assert_eq!(output, "4\n");
+        assert_eq!(ints(&vm), [4]);
+    }
+
+    #[test]
+    fn nand_nor_xnor_truth_tables() {
+        for (a, b) in [(0, 0), (0, 1), (1, 0), (1, 1), (12, 10)] {
+            let (vm, result, _) = run_source(&format!("PSH {a}\nPSH {b}\nNND\nPSH {a}\nPSH {b}\nNOR\nPSH {a}\nPSH {b}\nXNR"));
+            result.unwrap();
+            assert_eq!(ints(&vm), [!(a & b), !(a | b), !(a ^ b)], "{} {}", a, b);
+            let (vm, _, _) = run_source(&format!("PSH {a}\nSET 0\nPSH {b}\nSET 1\nNND 0 1\nNOR 0 1\nXNR 0 1"));
+            assert_eq!(ints(&vm), [!(a & b), !(a | b), !(a ^ b)], "{} {}", a, b);
+        }
+    }
+
+    #[test]
+    fn underflow_leaves_the_stack_unchanged() {
+        let one_value = ["ADD", "SUB", "MUL", "DIV", "MOD", "MIN", "MAX", "POW", "GCD", "AND", "OR", "XOR", "NND", "NOR", "XNR",
+            "SHL", "SHR", "EQU", "NEQ", "GTH", "LTH", "GTE", "LTE", "ANL", "ORL", "SWP", "OVER", "ROT", "PEK 1", "DRP 2",
+            "DPN 2", "SEL", "STRI", "SETI"];
+        for opcode in one_value {
+            let (vm, result, _) = run_source(&format!("PSH 5\n{}", opcode));
+            assert!(matches!(result, Err(VmError::StackUnderflow { pc: 1, .. })), "{}: {:?}", opcode, result);
+            assert_eq!(ints(&vm), [5], "{}", opcode);
+        }
+        let (vm, result, _) = run_source("PSHF 1.5\nPSH 5\nFADD");
+        assert!(matches!(result, Err(VmError::TypeMismatch { .. })), "{:?}", result);
+        assert_eq!(vm.stack(), [Value::Float(1.5), Value::Int(5)]);
+        for opcode in ["POP", "DUP", "NEG", "PRT", "PPT", "PRC", "JMI", "EXT", "FTOI", "ITOF", "LOAI", "GETI"] {
+            let (vm, result, _) = run_source(opcode);
+            assert!(matches!(result, Err(VmError::StackUnderflow { pc: 0, .. })), "{}: {:?}", opcode, result);
+            assert!(vm.stack().is_empty());
+        }
+    }
+
+    #[test]
+    fn jump_to_an_lbl_anchor() {
+        let (vm, result, _) = run_source("PSH 1\nJMP target\nPSH 2\nLBL target\nPSH 3");
+        result.unwrap();
+        assert_eq!(ints(&vm), [1, 3]);
+        assert_eq!(vm.resolve_label("target"), Some(3));
+        assert_eq!(vm.instructions_executed(), 4);
+    }
+
+    #[test]
+    fn instruction_count_and_halt_reason() {
+        let (vm, result, _) = run_source("PSH 1\nPSH 2\nADD\nHLT\nPSH 3");
+        result.unwrap();
+        assert_eq!(vm.instructions_executed(), 4);
+        assert_eq!(vm.halt_reason(), Some(HaltReason::Halted));
+        let (vm, _, _) = run_source("PSH 1\nPOP");
+        assert_eq!(vm.instructions_executed(), 2);
+        assert_eq!(vm.halt_reason(), Some(HaltReason::EndOfProgram));
+        let (vm, _, _) = run_source("PSH 1\nPOP\nPOP");
+        assert_eq!(vm.instructions_executed(), 2);
+        assert_eq!(vm.halt_reason(), Some(HaltReason::Error));
+        let (vm, _) = load_source("NOP").unwrap();
+        assert_eq!(vm.halt_reason(), None);
+    }
+
+    #[test]
+    fn dup_and_swp() {
+        let (vm, result, _) = run_source("PSH 1\nPSH 2\nDUP\nPSH 3\nSWP\nPSHF 0.5\nDUP\nSWP");
+        result.unwrap();
+        assert_eq!(vm.stack(), [Value::Int(1), Value::Int(2), Value::Int(3), Value::Int(2), Value::Float(0.5), Value::Float(0.5)]);
+        let config = VmConfig { max_stack: 1, ..VmConfig::default() };
+        let (vm, result, _) = run_source_with(config, "PSH 1\nDUP");
+        assert!(matches!(result, Err(VmError::StackOverflow { limit: 1, pc: 1 })), "{:?}", result);
+        assert_eq!(ints(&vm), [1]);
+    }
+
+    #[test]
+    fn registers_indexed_from_the_stack() {
+        let (vm, result, _) = run_source("PSH 42\nSET 3\nPSH 3\nGETI\nPSH 7\nPSH 5\nSETI\nGET 5");
+        result.unwrap();
+        assert_eq!(ints(&vm), [42, 7]);
+        let (vm, result, _) = run_source("PSH 7\nPSH 8\nSETI");
+        assert!(matches!(result, Err(VmError::InvalidRegister { index: 8, pc: 2 })), "{:?}", result);
+        assert_eq!(ints(&vm), [7, 8]);
+    }
+
+    #[test]
+    fn sel_picks_a_value_by_condition() {
+        let (vm, result, _) = run_source("PSH 10\nPSH 20\nPSH 1\nSEL\nPSH 10\nPSH 20\nPSH 0\nSEL\nPSH 10\nPSH 20\nPSH -3\nSEL");
+        result.unwrap();
+        assert_eq!(ints(&vm), [20, 10, 20]);
+        let (vm, result, _) = run_source("PSH 20\nPSH 1\nSEL");
+        assert!(matches!(result, Err(VmError::StackUnderflow { opcode: Opcode::SEL, pc: 2 })), "{:?}", result);
+        assert_eq!(ints(&vm), [20, 1]);
+    }
+
+    #[test]
+    fn strict_halt_rejects_leftover_values() {
+        let strict = VmConfig { strict_halt: true, ..VmConfig::default() };
+        let (vm, result, _) = run_source_with(strict, "PSH 1\nPSH 2\nHLT");
+        assert!(matches!(result, Err(VmError::NonEmptyStackAtHalt { remaining: 2, pc: 2 })), "{:?}", result);
+        assert_eq!(vm.exit_code(), None);
+        let (vm, result, _) = run_source_with(strict, "PSH 1\nPOP\nHLT");
+        result.unwrap();
+        assert_eq!(vm.exit_code(), Some(0));
+        let (_, result, _) = run_source("PSH 1\nHLT");
+        result.unwrap();
+    }
+
+    #[test]
+    fn register_inc_and_dec_follow_the_overflow_mode() {
+        let checked = VmConfig { overflow: OverflowMode::Checked, ..VmConfig::default() };
+        let (vm, result, _) = run_source_with(checked, "PSH 2147483647\nSET 0\nINC 0");
+        assert!(matches!(result, Err(VmError::ArithmeticOverflow { pc: 2 })), "{:?}", result);
+        assert_eq!(vm.registers()[0], i32::MAX);
+        let (_, result, _) = run_source_with(checked, "PSH -2147483648\nSET 0\nDEC 0");
+        assert!(matches!(result, Err(VmError::ArithmeticOverflow { pc: 2 })), "{:?}", result);
+        let (vm, result, _) = run_source("PSH 2147483647\nSET 0\nINC 0");
+        result.unwrap();
+        assert_eq!(vm.registers()[0], i32::MIN);
+    }
+
+    #[test]
+    fn fil_writes_a_value_to_a_range() {
+        let (vm, result, _) = run_source("PSH 7\nFIL 10 5\nLOA 9\nLOA 10\nLOA 11\nLOA 12\nLOA 13\nLOA 14\nLOA 15");
+        result.unwrap();
+        assert_eq!(ints(&vm), [0, 7, 7, 7, 7, 7, 0]);
+        assert_eq!(vm.memory_high_water(), Some(14));
+        let (vm, result, _) = run_source("PSH 7\nFIL 1048575 2");
+        assert!(matches!(result, Err(VmError::MemoryOutOfBounds { .. })), "{:?}", result);
+        assert_eq!(ints(&vm), [7]);
+        assert_eq!(vm.memory_high_water(), None);
+    }
+
+    #[test]
+    fn mcp_copies_a_region() {
+        let fill = "PSH 1\nSTR 0\nPSH 2\nSTR 1\nPSH 3\nSTR 2\nPSH 4\nSTR 3\n";
+        let (vm, result, _) = run_source(&format!("{fill}PSH 4\nMCP 10 0\nLOA 10\nLOA 11\nLOA 12\nLOA 13\nLOA 14"));
+        result.unwrap();
+        assert_eq!(ints(&vm), [1, 2, 3, 4, 0]);
+    }
+
+    #[test]
+    fn mcp_handles_overlapping_regions() {
+        let fill = "PSH 1\nSTR 0\nPSH 2\nSTR 1\nPSH 3\nSTR 2\nPSH 4\nSTR 3\n";
+        let (vm, result, _) = run_source(&format!("{fill}PSH 4\nMCP 2 0\nLOA 0\nLOA 1\nLOA 2\nLOA 3\nLOA 4\nLOA 5"));
+        result.unwrap();
+        assert_eq!(ints(&vm), [1, 2, 1, 2, 3, 4]);
+        let (vm, result, _) = run_source(&format!("{fill}PSH 3\nMCP 0 1\nLOA 0\nLOA 1\nLOA 2\nLOA 3"));
+        result.unwrap();
+        assert_eq!(ints(&vm), [2, 3, 4, 4]);
+    }
+
+    #[test]
+    fn mcp_with_zero_length_copies_nothing() {
+        let (vm, result, _) = run_source("PSH 0\nMCP 5 10");
+        result.unwrap();
+        assert!(vm.stack().is_empty());
+        assert_eq!(vm.memory_high_water(), None);
+        let (vm, result, _) = run_source("PSH 9\nSTR 0\nPSH 0\nMCP 0 100\nLOA 0");
+        result.unwrap();
+        assert_eq!(ints(&vm), [9]);
+    }
+
+    #[test]
+    fn mcp_out_of_bounds_copies_nothing() {
+        let (vm, result, _) = run_source("PSH 2\nMCP 1048575 0\nLOA 1048575");
+        assert!(matches!(result, Err(VmError::MemoryOutOfBounds { .. })), "{:?}", result);
+        assert_eq!(ints(&vm), [2]);
+    }
+
+    #[test]
+    fn memory_high_water_tracks_the_highest_store() {
+        let (mut vm, result, _) = run_source("PSH 1\nSTR 500\nPSH 2\nSTR 3\nPSH 3\nPSH 9000\nSTRI\nPSH 20\nSET 0\nPSH 4\nSTRR 0\nLOA 20000");
+        result.unwrap();
+        assert_eq!(vm.memory_high_water(), Some(9000));
+        vm.reset();
+        assert_eq!(vm.memory_high_water(), None);
+        let (vm, result, _) = run_source("LOA 100\nPSH 5\nLOAI");
+        result.unwrap();
+        assert_eq!(vm.memory_high_water(), None);
+    }
+
+    #[test]
+    fn pru_and_prx_print_the_unsigned_view() {
+        let (vm, result, output) = run_source("PSH -1\nPRU\nPRX\nPSH 255\nPRU\nPRX");
+        result.unwrap();
+        assert_eq!(output, "4294967295\n0xFFFFFFFF\n255\n0x000000FF\n");
+        assert_eq!(ints(&vm), [-1, 255]);
+        let (_, result, _) = run_source("PSHF 1.5\nPRX");
+        assert!(matches!(result, Err(VmError::TypeMismatch { opcode: Opcode::PRX, pc: 1 })), "{:?}", result);
+        let (_, result, _) = run_source("PRU");
+        assert!(matches!(result, Err(VmError::StackUnderflow { opcode: Opcode::PRU, pc: 0 })), "{:?}", result);
+    }
+
+    #[test]
+    fn deadline_stops_a_tight_loop() {
+        let (mut vm, _) = load_source("loop:\nJMP loop").unwrap();
+        let start = Instant::now();
+        let result = vm.run_with_deadline(Duration::from_millis(20));
+        assert!(matches!(result, Err(VmError::TimeLimitExceeded { .. })), "{:?}", result);
+        assert!(start.elapsed() < Duration::from_secs(5));
+        assert_eq!(vm.halt_reason(), Some(HaltReason::LimitExceeded));
+        vm.reset();
+        let result = vm.run_with_limits(Some(500), Some(Duration::from_secs(60)));
+        assert!(matches!(result, Err(VmError::InstructionLimitExceeded { limit: 500, .. })), "{:?}", result);
+    }
+
+    // Hands out one line per read and takes a while for each, like a user typing slowly
+    struct SlowInput {
+        lines: Vec<u8>,
+        position: usize,
+    }
+
+    impl std::io::Read for SlowInput {
+        fn read(&mut self, buf: &mut [u8]) -> std::io::Result<usize> {
+            let available = self.fill_buf()?;
+            let length = available.len().min(buf.len());
+            buf[..length].copy_from_slice(&available[..length]);
+            self.consume(length);
+            Ok(length)
+        }
+    }
+
+    impl BufRead for SlowInput {
+        fn fill_buf(&mut self) -> std::io::Result<&[u8]> {
+            std::thread::sleep(Duration::from_millis(50));
+            let rest = &self.lines[self.position..];
+            let end = rest.iter().position(|&byte| byte == b'\n').map_or(rest.len(), |index| index + 1);
+            Ok(&rest[..end])
+        }
+
+        fn consume(&mut self, amount: usize) {
+            self.position += amount;
+        }
+    }
+
+    #[test]
+    fn deadline_stops_a_program_waiting_on_input() {
+        let input = SlowInput { lines: "1\n".repeat(30).into_bytes(), position: 0 };
+        let (vm, _) = load_source("PSH 30\nSET 0\nloop:\nINP\nPOP\nDEC 0\nGET 0\nJNZP loop").unwrap();
+        let mut vm = vm.with_input(input);
+        let start = Instant::now();
+        let result = vm.run_with_deadline(Duration::from_millis(100));
+        assert!(matches!(result, Err(VmError::TimeLimitExceeded { .. })), "{:?}", result);
+        assert!(start.elapsed() < Duration::from_millis(1000), "{:?}", start.elapsed());
+        assert!(vm.registers()[0] > 0);
+    }
+
+    #[test]
+    fn time_reads_are_monotonic() {
+        let (vm, result, _) = run_source("TIM\nTIM\nLTE\nTIMM\nTIMM");
+        result.unwrap();
+        let stack = ints(&vm);
+        assert_eq!(stack[0], 1);
+        let millis = |high: i32, low: i32| ((high as u32 as u64) << 32) | low as u32 as u64;
+        let (first, second) = (millis(stack[1], stack[2]), millis(stack[3], stack[4]));
+        assert!(first <= second);
+        assert!(first.abs_diff(epoch_time().as_millis() as u64) < 60_000);
+    }
+
+    #[test]
+    fn unbounded_recursion_hits_the_call_depth() {
+        let config = VmConfig { max_call_depth: 8, ..VmConfig::default() };
+        let (vm, result, _) = run_source_with(config, "PSH 0\nrecurse:\nINC\nCALL recurse");
+        assert!(matches!(result, Err(VmError::CallStackOverflow { depth: 8, pc: 2 })), "{:?}", result);
+        assert_eq!(ints(&vm), [9]);
+        let (mut vm, _) = load_source("CALL f\nHLT\nf:\nCALL g\nRET\ng:\nRET").unwrap();
+        vm.set_max_call_depth(1);
+        assert!(matches!(vm.run(), Err(VmError::CallStackOverflow { depth: 1, pc: 2 })));
+        vm.reset();
+        vm.set_max_call_depth(2);
+        vm.run().unwrap();
+    }
+
+    #[test]
+    fn jumps_use_the_address_not_a_label_named_like_it() {
+        let (vm, result, _) = run_source("PSH 7\nJMP end\n3:\nPSH 99\nend:\nHLT");
+        result.unwrap();
+        assert_eq!(ints(&vm), [7]);
+        let (vm, result, _) = run_source("PSH 7\nPSH 0\nJEZ end\n4:\nPSH 99\nend:\nHLT");
+        result.unwrap();
+        assert_eq!(ints(&vm), [7, 0]);
+    }
+
+    #[test]
+    fn deb_output_is_the_same_on_every_run() {
+        let source = "zeta:\nPSH 5\nSTR 3\nalpha:\nmid:\nPSH 1\nCALL sub\nHLT\nsub:\nDEB\nRET\nomega:";
+        let (_, result, output) = run_source(source);
+        result.unwrap();
+        assert_eq!(output, "PC: 5, Stack: [1], Memory: [(3, 5)], Registers: [0, 0, 0, 0, 0, 0, 0, 0], Labels: {\"alpha\": 2, \"mid\": 2, \"omega\": 7, \"sub\": 5, \"zeta\": 0}, Call Stack: [4]\n");
+        for _ in 0..20 {
+            assert_eq!(run_source(source).2, output);
+        }
+    }
+}
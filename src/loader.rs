@@ -0,0 +1,848 @@
+use std::collections::HashMap;
+use std::io::BufRead;
+use std::path::{Path, PathBuf};
+use std::time::Duration;
+
+use crate::{Instruction, Opcode, VmError, VM};
+
+// Longer names accepted in place of the canonical mnemonics, the disassembler always prints the canonical ones
+const OPCODE_ALIASES: &[(&str, &str)] = &[
+    ("PUSH", "PSH"),
+    ("PUSHF", "PSHF"),
+    ("SWAP", "SWP"),
+    ("CLEARSTACK", "SCL"),
+    ("STORE", "STR"),
+    ("LOAD", "LOA"),
+    ("CLEARMEM", "MCL"),
+    ("SQRT", "SQR"),
+    ("RETURN", "RET"),
+    ("INPUT", "INP"),
+    ("PRINT", "PRT"),
+    ("PRINTPOP", "PPT"),
+    ("PRINTCHAR", "PRC"),
+    ("PRINTSTR", "PRS"),
+    ("HALT", "HLT"),
+    ("EXIT", "EXT"),
+];
+
+impl VM {
+    // Loads a program from an assembly file, along with every file it pulls in through "#include path" directives.
+    // Included code is placed after the including file's code, and its labels are prefixed with the included
+    // file's name (e.g. "math.square" for label "square" in math.vm).
+    pub fn load_program_from_file(&mut self, filename: &str) -> Result<(), VmError> {
+        let mut units = Vec::new();
+        collect_source_units(Path::new(filename), None, &mut Vec::new(), &mut units)?;
+        let mut program = Vec::new();
+        let mut labels = HashMap::new();
+        let mut constants = HashMap::new();
+        
+        // First pass: collect all labels and their positions, and the .define constants
+        let mut current_position = 0;
+        for unit in &units {
+            collect_labels(unit, &mut labels, &mut constants, &mut current_position).map_err(|error| unit.locate(error))?;
+        }
+        
+        // Second pass: process instructions
+        for unit in &units {
+            assemble_unit(unit, &labels, &constants, &mut program).map_err(|error| unit.locate(error))?;
+        }
+
+        self.labels = labels;
+        self.load_program(program);
+        Ok(())
+    }
+
+    // Appends the statements of a single line to the program and runs from the first of them, keeping the
+    // stack, memory, registers and labels of earlier lines. Meant for interactive use, see the --repl flag.
+    // Labels can only be jumped to once they have been defined on an earlier or the same line, and jumping
+    // back to one runs everything entered since then again. .define and #include are not supported.
+    pub fn exec_line(&mut self, line: &str) -> Result<(), VmError> {
+        self.exec_line_with_limits(line, None, None)
+    }
+
+    // Like exec_line, but runs the line with the limits of run_with_limits
+    pub fn exec_line_with_limits(&mut self, line: &str, max_steps: Option<u64>, time_limit: Option<Duration>) -> Result<(), VmError> {
+        let start = self.program.len();
+        let mut labels = self.labels.clone();
+        let mut statements = Vec::new();
+
+        // Define the labels first so a jump can refer to a label later on the same line, like in a file
+        let mut position = start;
+        for statement in split_statements(line) {
+            if statement.starts_with('#') {
+                break;
+            }
+            if statement.starts_with('.') {
+                return Err(VmError::ParseError { line: 1, message: "directives can't be used on a single line".to_string() });
+            }
+            if let Some(label) = label_definition(statement, 1)? {
+                define_label(&mut labels, label.to_string(), position, 1)?;
+                continue;
+            }
+            if let Some(label) = lbl_label(statement, 1)? {
+                define_label(&mut labels, label.to_string(), position, 1)?;
+            }
+            position += match string_literal_operand(statement) {
+                Some(literal) => parse_string_literal(literal).map_err(|message| VmError::ParseError { line: 1, message })?.len(),
+                None => 1,
+            };
+            statements.push(statement);
+        }
+
+        // Nothing is added to the program unless the whole line parses
+        let mut program = Vec::new();
+        for statement in statements {
+            parse_statement(statement, &labels, &HashMap::new(), None, 1, &mut program)?;
+        }
+        self.labels = labels;
+        self.program.extend(program);
+        self.pc = start;
+        self.run_with_limits(max_steps, time_limit)
+    }
+
+    // Turns the loaded program back into assembly, one "index: MNEMONIC operands" line per instruction
+    pub fn disassemble(&self) -> String {
+        let mut output = String::new();
+        for (index, &(opcode, operand_1, operand_2)) in self.program.iter().enumerate() {
+            output.push_str(&format!("{}: {:?}", index, opcode));
+            if let Some(operand_1) = operand_1 {
+                match self.label_at(operand_1) {
+                    Some(label) if opcode.is_jump() || opcode == Opcode::LBL => output.push_str(&format!(" {}", label)),
+                    _ if opcode == Opcode::PSHF => output.push_str(&format!(" {:?}", f32::from_bits(operand_1 as u32))),
+                    _ => output.push_str(&format!(" {}", operand_1)),
+                }
+            }
+            if let Some(operand_2) = operand_2 {
+                output.push_str(&format!(" {}", operand_2));
+            }
+            output.push('\n');
+        }
+        output
+    }
+
+    // Finds a label at the given position, preferring the alphabetically first one if several share it
+    fn label_at(&self, position: i32) -> Option<&str> {
+        self.labels.iter()
+            .filter(|(_, &label_position)| label_position as i32 == position)
+            .map(|(name, _)| name.as_str())
+            .min()
+    }
+}
+
+// Splits a line into its ';' separated statements, trimmed and without empty ones, after cutting off
+// a trailing "// comment". ';' and "//" inside character and string literals are kept.
+fn split_statements(line: &str) -> Vec<&str> {
+    let mut statements = Vec::new();
+    let mut start = 0;
+    let mut end = line.len();
+    let mut quote = None;
+    let mut escaped = false;
+    for (index, ch) in line.char_indices() {
+        match quote {
+            Some(_) if escaped => escaped = false,
+            Some(_) if ch == '\\' => escaped = true,
+            Some(open) if ch == open => quote = None,
+            Some(_) => {},
+            None if ch == '\'' || ch == '"' => quote = Some(ch),
+            None if ch == ';' => {
+                statements.push(&line[start..index]);
+                start = index + 1;
+            },
+            None if line[index..].starts_with("//") => {
+                end = index;
+                break;
+            },
+            None => {},
+        }
+    }
+    statements.push(&line[start..end]);
+    statements.into_iter().map(str::trim).filter(|statement| !statement.is_empty()).collect()
+}
+
+// Splits a line on whitespace, keeping quoted character literals such as ' ' together
+fn split_tokens(line: &str) -> Vec<&str> {
+    let mut tokens = Vec::new();
+    let mut start = None;
+    let mut in_quotes = false;
+    let mut escaped = false;
+    for (index, ch) in line.char_indices() {
+        if in_quotes {
+            if escaped {
+                escaped = false;
+            } else if ch == '\\' {
+                escaped = true;
+            } else if ch == '\'' {
+                in_quotes = false;
+            }
+        } else if ch.is_whitespace() {
+            if let Some(token_start) = start.take() {
+                tokens.push(&line[token_start..index]);
+            }
+        } else {
+            if start.is_none() {
+                start = Some(index);
+            }
+            if ch == '\'' {
+                in_quotes = true;
+            }
+        }
+    }
+    if let Some(token_start) = start {
+        tokens.push(&line[token_start..]);
+    }
+    tokens
+}
+
+// Parses an integer operand (decimal, 0x hexadecimal, 0b binary or 0o octal) or a character literal such as 'A' or '\n'
+fn parse_integer(token: &str) -> Option<i32> {
+    if let Some(literal) = token.strip_prefix('\'').and_then(|rest| rest.strip_suffix('\'')) {
+        let mut chars = literal.chars();
+        let ch = match (chars.next()?, chars.next(), chars.next()) {
+            ('\\', Some(escape), None) => match escape {
+                'n' => '\n',
+                't' => '\t',
+                '0' => '\0',
+                '\\' => '\\',
+                '\'' => '\'',
+                _ => return None,
+            },
+            (ch, None, None) => ch,
+            _ => return None,
+        };
+        return Some(ch as i32);
+    }
+
+    // Hexadecimal, binary and octal literals, optionally negative. Like in Rust, underscores can separate
+    // digits (1_000_000, 0xFF_FF) but can't start the number
+    let (negative, digits) = match token.strip_prefix('-') {
+        Some(rest) => (true, rest),
+        None => (false, token),
+    };
+    if digits.starts_with('_') {
+        return None;
+    }
+    let digits = digits.replace('_', "");
+    let radix = match digits.get(..2) {
+        Some("0x") | Some("0X") => 16,
+        Some("0b") | Some("0B") => 2,
+        Some("0o") | Some("0O") => 8,
+        _ => return token.replace('_', "").parse().ok(),
+    };
+    // Up to 32 bits are accepted so bit patterns such as 0xFFFFFFFF can be written directly
+    let magnitude = u32::from_str_radix(&digits[2..], radix).ok()?;
+    if negative {
+        i32::try_from(-(magnitude as i64)).ok()
+    } else {
+        Some(magnitude as i32)
+    }
+}
+
+// Resolves an operand token as a label position, a .define constant or an integer literal
+fn parse_operand(token: &str, labels: &HashMap<String, usize>, constants: &HashMap<String, i32>, namespace: Option<&str>, line: usize) -> Result<i32, VmError> {
+    if let Some(position) = lookup_label(labels, namespace, token) {
+        Ok(position as i32)
+    } else if let Some(&value) = constants.get(token) {
+        Ok(value)
+    } else {
+        parse_integer(token).ok_or_else(|| VmError::BadOperand { token: token.to_string(), line })
+    }
+}
+
+// Parses a ".define NAME value" line into the constant's name and value
+fn parse_directive(line: &str) -> Result<(&str, i32), String> {
+    let tokens = split_tokens(line);
+    match tokens.as_slice() {
+        [".define", name, value] => {
+            if parse_integer(name).is_some() {
+                return Err(format!("invalid constant name '{}'", name));
+            }
+            let value = parse_integer(value).ok_or_else(|| format!("invalid constant value '{}'", value))?;
+            Ok((name, value))
+        },
+        [".define", ..] => Err("expected '.define NAME value'".to_string()),
+        [directive, ..] => Err(format!("unknown directive '{}'", directive)),
+        [] => Err("empty directive".to_string()),
+    }
+}
+
+// Looks a label up in the file's own namespace first, then as written (which covers "file.label" references)
+fn lookup_label(labels: &HashMap<String, usize>, namespace: Option<&str>, token: &str) -> Option<usize> {
+    namespace
+        .and_then(|namespace| labels.get(&format!("{}.{}", namespace, token)))
+        .or_else(|| labels.get(token))
+        .copied()
+}
+
+// The lines of one source file, namespace is None for the file passed to load_program_from_file
+struct SourceUnit {
+    path: PathBuf,
+    namespace: Option<String>,
+    lines: Vec<String>,
+}
+
+impl SourceUnit {
+    // Every statement with its (0-based) line number, a line starting with '#' is a comment as a whole
+    fn statements(&self) -> impl Iterator<Item = (usize, &str)> {
+        self.lines.iter().enumerate()
+            .filter(|(_, line)| !line.trim_start().starts_with('#'))
+            .flat_map(|(line_number, line)| split_statements(line).into_iter().map(move |statement| (line_number, statement)))
+    }
+
+    fn qualify(&self, label: &str) -> String {
+        match &self.namespace {
+            Some(namespace) => format!("{}.{}", namespace, label),
+            None => label.to_string(),
+        }
+    }
+
+    // Line numbers of an included file are meaningless without its path, errors of the main file are left as is
+    fn locate(&self, error: VmError) -> VmError {
+        match self.namespace {
+            Some(_) => VmError::InIncludedFile { path: self.path.clone(), error: Box::new(error) },
+            None => error,
+        }
+    }
+}
+
+// Defines the labels and .define constants of a unit, advancing current_position past its instructions
+fn collect_labels(unit: &SourceUnit, labels: &mut HashMap<String, usize>, constants: &mut HashMap<String, i32>, current_position: &mut usize) -> Result<(), VmError> {
+    for (line_number, line) in unit.statements() {
+        // Skip comments and empty lines
+        if line.is_empty() || line.starts_with('#') {
+            continue;
+        }
+        
+        // Check for label definition (ends with ':')
+        if let Some(label) = label_definition(line, line_number + 1)? {
+            define_label(labels, unit.qualify(label), *current_position, line_number + 1)?;
+            continue;
+        }
+
+        if line.starts_with('.') {
+            let (name, value) = parse_directive(line).map_err(|message| VmError::ParseError { line: line_number + 1, message })?;
+            if constants.insert(name.to_string(), value).is_some() {
+                return Err(VmError::ParseError { line: line_number + 1, message: format!("constant '{}' defined again", name) });
+            }
+            continue;
+        }
+
+        if let Some(label) = lbl_label(line, line_number + 1)? {
+            define_label(labels, unit.qualify(label), *current_position, line_number + 1)?;
+        }
+        
+        // Count instruction, a string literal expands into one PSH per character
+        if let Some(literal) = string_literal_operand(line) {
+            *current_position += parse_string_literal(literal)
+                .map_err(|message| VmError::ParseError { line: line_number + 1, message })?
+                .len();
+        } else {
+            *current_position += 1;
+        }
+    }
+    Ok(())
+}
+
+// Appends the instructions of a unit to the program
+fn assemble_unit(unit: &SourceUnit, labels: &HashMap<String, usize>, constants: &HashMap<String, i32>, program: &mut Vec<Instruction>) -> Result<(), VmError> {
+    let namespace = unit.namespace.as_deref();
+    for (line_number, line) in unit.statements() {
+        // Skip comments, empty lines, labels and directives
+        if line.is_empty() || line.starts_with('#') || line.ends_with(':') || line.starts_with('.') {
+            continue;
+        }
+        
+        parse_statement(line, labels, constants, namespace, line_number + 1, program)?;
+    }
+    Ok(())
+}
+
+// Reads a source file followed by everything it includes, depth first. A file that was already read is
+// skipped, including a file that is still being read (a circular include) is an error.
+fn collect_source_units(path: &Path, namespace: Option<String>, including: &mut Vec<PathBuf>, units: &mut Vec<SourceUnit>) -> Result<(), VmError> {
+    let canonical = std::fs::canonicalize(path)?;
+    let reader = std::io::BufReader::new(std::fs::File::open(path)?);
+    let lines: Vec<String> = reader.lines().collect::<Result<_, _>>()?;
+
+    let directory = path.parent().unwrap_or(Path::new(""));
+    let includes: Vec<(usize, PathBuf)> = lines.iter().enumerate()
+        .filter_map(|(line_number, line)| include_directive(line.trim()).map(|target| (line_number + 1, directory.join(target))))
+        .collect();
+
+    including.push(canonical.clone());
+    let included = namespace.is_some();
+    units.push(SourceUnit { path: canonical.clone(), namespace, lines });
+    for (line, include_path) in includes {
+        let target = std::fs::canonicalize(&include_path)?;
+        if including.contains(&target) {
+            let error = VmError::ParseError { line, message: format!("circular include of '{}'", include_path.display()) };
+            return Err(if included { VmError::InIncludedFile { path: canonical, error: Box::new(error) } } else { error });
+        }
+        if units.iter().any(|unit| unit.path == target) {
+            continue;
+        }
+        let namespace = include_path.file_stem().map(|stem| stem.to_string_lossy().into_owned());
+        collect_source_units(&include_path, namespace, including, units)?;
+    }
+    including.pop();
+    Ok(())
+}
+
+// Returns the path of an "#include path" line, the path may be quoted
+fn include_directive(line: &str) -> Option<&str> {
+    let rest = line.strip_prefix("#include")?;
+    if !rest.starts_with(char::is_whitespace) {
+        return None;
+    }
+    let path = rest.trim();
+    let path = path.strip_prefix('"').and_then(|rest| rest.strip_suffix('"')).unwrap_or(path);
+    if path.is_empty() { None } else { Some(path) }
+}
+
+// Returns the name of a "label:" statement, None for any other statement
+fn label_definition(line: &str, line_number: usize) -> Result<Option<&str>, VmError> {
+    let Some(label) = line.strip_suffix(':') else {
+        return Ok(None);
+    };
+    let label = label.trim();
+    if label.is_empty() || label.contains(char::is_whitespace) {
+        return Err(VmError::ParseError { line: line_number, message: format!("invalid label name '{}'", label) });
+    }
+    Ok(Some(label))
+}
+
+// "LBL name" defines a label like "name:", but at its own (NOP-like) instruction. Returns the name for a
+// LBL statement, None for any other statement
+fn lbl_label(line: &str, line_number: usize) -> Result<Option<&str>, VmError> {
+    let tokens = split_tokens(line);
+    if !tokens[0].eq_ignore_ascii_case("LBL") {
+        return Ok(None);
+    }
+    match tokens[1..] {
+        [label] if parse_integer(label).is_none() => Ok(Some(label)),
+        _ => Err(VmError::ParseError { line: line_number, message: "LBL takes a single label name".to_string() }),
+    }
+}
+
+fn define_label(labels: &mut HashMap<String, usize>, label: String, position: usize, line_number: usize) -> Result<(), VmError> {
+    if labels.contains_key(&label) {
+        return Err(VmError::DuplicateLabel { name: label, line: line_number });
+    }
+    labels.insert(label, position);
+    Ok(())
+}
+
+// Parses one statement into its instructions (several for a PSH "text" literal) and appends them to program.
+// line_number is 1-based.
+fn parse_statement(line: &str, labels: &HashMap<String, usize>, constants: &HashMap<String, i32>, namespace: Option<&str>, line_number: usize, program: &mut Vec<Instruction>) -> Result<(), VmError> {
+    // Expand string literals into one PSH per character
+    if let Some(literal) = string_literal_operand(line) {
+        let codes = parse_string_literal(literal)
+            .map_err(|message| VmError::ParseError { line: line_number, message })?;
+        for code in codes {
+            program.push((Opcode::PSH, Some(code), None));
+        }
+        return Ok(());
+    }
+
+    // Parse instruction
+    let mut parts = split_tokens(line).into_iter();
+    if let Some(opcode_str) = parts.next() {
+        let opcode = match canonical_mnemonic(opcode_str).as_str() {
+            "ADD" => Opcode::ADD,
+            "SUB" => Opcode::SUB,
+            "MUL" => Opcode::MUL,
+            "DIV" => Opcode::DIV,
+            "MOD" => Opcode::MOD,
+            "INC" => Opcode::INC,
+            "DEC" => Opcode::DEC,
+            "NEG" => Opcode::NEG,
+            "ABS" => Opcode::ABS,
+            "MIN" => Opcode::MIN,
+            "MAX" => Opcode::MAX,
+            "POW" => Opcode::POW,
+            "SQR" => Opcode::SQR,
+            "GCD" => Opcode::GCD,
+            "AND" => Opcode::AND,
+            "OR" => Opcode::OR,
+            "XOR" => Opcode::XOR,
+            "NND" => Opcode::NND,
+            "NOR" => Opcode::NOR,
+            "XNR" => Opcode::XNR,
+            "SHL" => Opcode::SHL,
+            "SHR" => Opcode::SHR,
+            "NOT" => Opcode::NOT,
+            "PSH" => Opcode::PSH,
+            "PSHF" => Opcode::PSHF,
+            "FADD" => Opcode::FADD,
+            "FSUB" => Opcode::FSUB,
+            "FMUL" => Opcode::FMUL,
+            "FDIV" => Opcode::FDIV,
+            "ITOF" => Opcode::ITOF,
+            "FTOI" => Opcode::FTOI,
+            "POP" => Opcode::POP,
+            "STR" => Opcode::STR,
+            "LOA" => Opcode::LOA,
+            "STRI" => Opcode::STRI,
+            "LOAI" => Opcode::LOAI,
+            "STRR" => Opcode::STRR,
+            "LOAR" => Opcode::LOAR,
+            "DUP" => Opcode::DUP,
+            "SWP" => Opcode::SWP,
+            "PEK" => Opcode::PEK,
+            "ROT" => Opcode::ROT,
+            "OVER" => Opcode::OVER,
+            "DRP" => Opcode::DRP,
+            "DPN" => Opcode::DPN,
+            "SEL" => Opcode::SEL,
+            "LEN" => Opcode::LEN,
+            "SCL" => Opcode::SCL,
+            "SET" => Opcode::SET,
+            "GET" => Opcode::GET,
+            "GETI" => Opcode::GETI,
+            "SETI" => Opcode::SETI,
+            "SWR" => Opcode::SWR,
+            "CLR" => Opcode::CLR,
+            "INP" => Opcode::INP,
+            "INPS" => Opcode::INPS,
+            "PRT" => Opcode::PRT,
+            "PPT" => Opcode::PPT,
+            "PRU" => Opcode::PRU,
+            "PRX" => Opcode::PRX,
+            "PRC" => Opcode::PRC,
+            "PRS" => Opcode::PRS,
+            "DEB" => Opcode::DEB,
+            "DMP" => Opcode::DMP,
+            "HLT" => Opcode::HLT,
+            "EXT" => Opcode::EXT,
+            "NOP" => Opcode::NOP,
+            "LBL" => Opcode::LBL,
+            "JMP" => Opcode::JMP,
+            "JEZ" => Opcode::JEZ,
+            "JNZ" => Opcode::JNZ,
+            "JGZ" => Opcode::JGZ,
+            "JLZ" => Opcode::JLZ,
+            "JEZP" => Opcode::JEZP,
+            "JNZP" => Opcode::JNZP,
+            "JGZP" => Opcode::JGZP,
+            "JLZP" => Opcode::JLZP,
+            "JMR" => Opcode::JMR,
+            "JMI" => Opcode::JMI,
+            "PCP" => Opcode::PCP,
+            "CALL" => Opcode::CALL,
+            "RET" => Opcode::RET,
+            "EQU" => Opcode::EQU,
+            "NEQ" => Opcode::NEQ,
+            "GTH" => Opcode::GTH,
+            "LTH" => Opcode::LTH,
+            "GTE" => Opcode::GTE,
+            "LTE" => Opcode::LTE,
+            "ANL" => Opcode::ANL,
+            "ORL" => Opcode::ORL,
+            "MCL" => Opcode::MCL,
+            "ALC" => Opcode::ALC,
+            "FRE" => Opcode::FRE,
+            "FIL" => Opcode::FIL,
+            "MCP" => Opcode::MCP,
+            "TIM" => Opcode::TIM,
+            "TIMM" => Opcode::TIMM,
+            "RND" => Opcode::RND,
+            "MOV" => Opcode::MOV,
+            "COP" => Opcode::COP,
+            _ => {
+                return Err(VmError::UnknownOpcode { text: opcode_str.to_string(), line: line_number });
+            }
+        };
+        if !opcode.operand_spec().contains(&parts.len()) {
+            return Err(VmError::ArityMismatch { opcode, line: line_number });
+        }
+
+        let operand_1 = match parts.next() {
+            // A jump target that isn't a number must name a label
+            Some(token) if opcode.is_jump() && lookup_label(labels, namespace, token).is_none() && !constants.contains_key(token) && parse_integer(token).is_none() => {
+                return Err(VmError::UndefinedLabel { name: token.to_string(), line: line_number });
+            },
+            // Floats are stored in the operand as their IEEE 754 bit pattern
+            Some(token) if opcode == Opcode::PSHF => {
+                let value: f32 = token.parse().map_err(|_| VmError::BadOperand { token: token.to_string(), line: line_number })?;
+                Some(value.to_bits() as i32)
+            },
+            Some(token) => Some(parse_operand(token, labels, constants, namespace, line_number)?),
+            None => None,
+        };
+        let operand_2 = parts.next().map(|token| parse_operand(token, labels, constants, namespace, line_number)).transpose()?;
+
+        program.push((opcode, operand_1, operand_2));
+    }
+    Ok(())
+}
+
+// Uppercases a mnemonic and replaces an alias with the mnemonic it stands for
+fn canonical_mnemonic(token: &str) -> String {
+    let mnemonic = token.to_uppercase();
+    match OPCODE_ALIASES.iter().find(|&&(alias, _)| alias == mnemonic) {
+        Some(&(_, canonical)) => canonical.to_string(),
+        None => mnemonic,
+    }
+}
+
+// Returns the quoted operand of a PSH "text" line
+fn string_literal_operand(line: &str) -> Option<&str> {
+    let (opcode, operand) = line.split_once(char::is_whitespace)?;
+    let operand = operand.trim();
+    if canonical_mnemonic(opcode) == "PSH" && operand.starts_with('"') {
+        Some(operand)
+    } else {
+        None
+    }
+}
+
+// Turns a quoted string literal into character codes, handling the \n, \t, \0, \\ and \" escapes
+fn parse_string_literal(literal: &str) -> Result<Vec<i32>, String> {
+    let mut chars = literal.chars();
+    if chars.next() != Some('"') {
+        return Err("string literal must start with '\"'".to_string());
+    }
+
+    let mut codes = Vec::new();
+    loop {
+        match chars.next() {
+            Some('"') => break,
+            Some('\\') => {
+                let escaped = match chars.next() {
+                    Some('n') => '\n',
+                    Some('t') => '\t',
+                    Some('0') => '\0',
+                    Some('\\') => '\\',
+                    Some('"') => '"',
+                    Some(other) => return Err(format!("unknown escape sequence '\\{}'", other)),
+                    None => return Err("unterminated string literal".to_string()),
+                };
+                codes.push(escaped as i32);
+            },
+            Some(ch) => codes.push(ch as i32),
+            None => return Err("unterminated string literal".to_string()),
+        }
+    }
+
+    if chars.next().is_some() {
+        return Err("unexpected text after string literal".to_string());
+    }
+    Ok(codes)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_util::*;
+
+    #[test]
+    fn disassembly_lists_instructions_in_order() {
+        let (vm, _) = load_source("PSH 3\nloop:\nDEC\nJNZ loop\nMOV 1 2\nPSHF 1.5\nHLT").unwrap();
+        assert_eq!(vm.disassemble(), "0: PSH 3\n1: DEC\n2: JNZ loop\n3: MOV 1 2\n4: PSHF 1.5\n5: HLT\n");
+    }
+
+    #[test]
+    fn string_literals_push_every_character() {
+        let (vm, result, output) = run_source("PSH \"AB\"\nPRC\nPRC");
+        result.unwrap();
+        assert_eq!(output, "BA");
+        assert!(vm.stack().is_empty());
+        let (vm, result, _) = run_source("PSH \"a\\n\\t\\\\\\\"\\0\"\nend:\nLEN");
+        result.unwrap();
+        assert_eq!(ints(&vm), [97, 10, 9, 92, 34, 0, 6]);
+        assert_eq!(vm.resolve_label("end"), Some(6));
+    }
+
+    #[test]
+    fn character_literals() {
+        let (vm, result, output) = run_source("PSH 'A'\nPRC\nPSH '\\n'\nPSH '\\t'\nPSH '\\0'\nPSH ' '");
+        result.unwrap();
+        assert_eq!(output, "A");
+        assert_eq!(ints(&vm), [10, 9, 0, 32]);
+    }
+
+    #[test]
+    fn prefixed_integer_literals() {
+        let (vm, result, _) = run_source("PSH 0xFF\nPSH 0b1000\nPSH 0o17\nPSH -0x10\nPSH 0xFFFFFFFF");
+        result.unwrap();
+        assert_eq!(ints(&vm), [255, 8, 15, -16, -1]);
+        assert!(matches!(load_source("PSH 0x1_0000_0000"), Err(VmError::BadOperand { line: 1, .. })));
+    }
+
+    #[test]
+    fn unknown_opcode_reports_its_line() {
+        let result = load_source("PSH 1\n\n# comment\nPHS 2\nADD");
+        assert!(matches!(result, Err(VmError::UnknownOpcode { ref text, line: 4 }) if text == "PHS"), "{:?}", result.err());
+    }
+
+    #[test]
+    fn duplicate_labels_are_rejected() {
+        let result = load_source("loop:\nNOP\nloop:\nJMP loop");
+        assert!(matches!(result, Err(VmError::DuplicateLabel { ref name, line: 3 }) if name == "loop"), "{:?}", result.err());
+    }
+
+    #[test]
+    fn undefined_jump_targets_are_rejected() {
+        let result = load_source("PSH 1\nJNZ nowhere");
+        assert!(matches!(result, Err(VmError::UndefinedLabel { ref name, line: 2 }) if name == "nowhere"), "{:?}", result.err());
+    }
+
+    #[test]
+    fn include_and_call_a_label_of_the_included_file() {
+        let directory = temp_directory();
+        std::fs::write(directory.join("math.vm"), "square:\nDUP\nMUL\nRET").unwrap();
+        let main = directory.join("main.vm");
+        std::fs::write(&main, "#include \"math.vm\"\nPSH 7\nCALL math.square\nHLT").unwrap();
+
+        let mut vm = VM::new();
+        vm.load_program_from_file(main.to_str().unwrap()).unwrap();
+        vm.run().unwrap();
+        assert_eq!(ints(&vm), [49]);
+        assert_eq!(vm.resolve_label("math.square"), Some(3));
+    }
+
+    #[test]
+    fn errors_in_an_included_file_name_the_file() {
+        let directory = temp_directory();
+        let math = directory.join("math.vm");
+        std::fs::write(&math, "square:\nDUP\nMLU\nRET").unwrap();
+        let main = directory.join("main.vm");
+        std::fs::write(&main, "#include \"math.vm\"\nPSH 7\nCALL math.square\nHLT").unwrap();
+
+        let error = VM::new().load_program_from_file(main.to_str().unwrap()).unwrap_err();
+        let VmError::InIncludedFile { path, error } = error else { panic!("{:?}", error) };
+        assert_eq!(path, std::fs::canonicalize(&math).unwrap());
+        assert!(matches!(*error, VmError::UnknownOpcode { line: 3, .. }), "{:?}", error);
+
+        // Errors in the main file stay as they are
+        std::fs::write(&main, "#include \"math.vm\"\nPSH 7\nCALL math.cube\nHLT").unwrap();
+        let error = VM::new().load_program_from_file(main.to_str().unwrap()).unwrap_err();
+        assert!(matches!(error, VmError::UndefinedLabel { line: 3, .. }), "{:?}", error);
+    }
+
+    #[test]
+    fn defined_constants_replace_operands() {
+        let (vm, result, _) = run_source(".define WIDTH 80\n.define NEWLINE '\\n'\nPSH WIDTH\nPSH NEWLINE");
+        result.unwrap();
+        assert_eq!(ints(&vm), [80, 10]);
+        let result = load_source(".define WIDTH 80\n.define WIDTH 40");
+        assert!(matches!(result, Err(VmError::ParseError { line: 2, .. })), "{:?}", result.err());
+    }
+
+    #[test]
+    fn one_line_program_runs_like_its_multi_line_form() {
+        let one_line = "PSH 3; loop:; DEC; DUP; PRT; POP; JNZ loop; PSH ';'; PRC; PSH \"a;b\"; LEN";
+        let multi_line = "PSH 3\nloop:\nDEC\nDUP\nPRT\nPOP\nJNZ loop\nPSH ';'\nPRC\nPSH \"a;b\"\nLEN";
+        let (one_line_vm, result, one_line_output) = run_source(one_line);
+        result.unwrap();
+        let (multi_line_vm, result, multi_line_output) = run_source(multi_line);
+        result.unwrap();
+        assert_eq!(one_line_vm.disassemble(), multi_line_vm.disassemble());
+        assert_eq!(one_line_vm.stack(), multi_line_vm.stack());
+        assert_eq!(one_line_output, multi_line_output);
+        assert_eq!(one_line_output, "2\n1\n0\n;");
+    }
+
+    #[test]
+    fn semicolon_always_separates_statements() {
+        assert_eq!(split_statements("PSH 5; add; end: // the end; PSH 6"), ["PSH 5", "add", "end:"]);
+        assert_eq!(split_statements("PSH 1;"), ["PSH 1"]);
+        assert_eq!(split_statements("; PSH 1 ;; PSH 2"), ["PSH 1", "PSH 2"]);
+        assert_eq!(split_statements("PSH ';' // ';'"), ["PSH ';'"]);
+        let result = load_source("PSH 5 ; counter");
+        assert!(matches!(result, Err(VmError::UnknownOpcode { line: 1, .. })), "{:?}", result.err());
+    }
+
+    #[test]
+    fn trailing_comments_are_ignored() {
+        let commented = ".define START 3 // first value\nPSH START // push five\nloop: // PSH the loop\nDEC //decrement\nJNZ loop // print it\nPSH ';' // return value\nADD // and then more; PSH 1\nHLT //";
+        let plain = ".define START 3\nPSH START\nloop:\nDEC\nJNZ loop\nPSH ';'\nADD\nHLT";
+        let (commented, _) = load_source(commented).unwrap();
+        let (plain, _) = load_source(plain).unwrap();
+        assert_eq!(commented.disassemble(), plain.disassemble());
+        assert_eq!(commented.labels(), plain.labels());
+    }
+
+    #[test]
+    fn operand_counts_are_checked() {
+        for (source, opcode) in [("PSH", Opcode::PSH), ("HLT 5", Opcode::HLT), ("ADD 1", Opcode::ADD), ("ADD 1 2 3", Opcode::ADD), ("NOP\nMOV 1", Opcode::MOV)] {
+            let result = load_source(source);
+            let line = source.lines().count();
+            assert!(matches!(result, Err(VmError::ArityMismatch { opcode: failed, line: failed_line }) if failed == opcode && failed_line == line), "{}: {:?}", source, result.err());
+        }
+        assert_eq!(VmError::ArityMismatch { opcode: Opcode::ADD, line: 1 }.to_string(), "Wrong number of operands for ADD on line 1, expected 0 or 2");
+    }
+
+    #[test]
+    fn hash_and_slash_comments() {
+        let commented = "# counts down\n// from three\n  // indented\nPSH 3 // start\nloop: // loop\nDEC\nJNZ loop // until zero\nPSH '/' // a quoted / stays";
+        let (commented, _) = load_source(commented).unwrap();
+        let (plain, _) = load_source("PSH 3\nloop:\nDEC\nJNZ loop\nPSH '/'").unwrap();
+        assert_eq!(commented.disassemble(), plain.disassemble());
+        assert_eq!(commented.labels(), plain.labels());
+    }
+
+    #[test]
+    fn bad_operands_name_the_token() {
+        let result = load_source("NOP\nPSH xyz");
+        assert!(matches!(result, Err(VmError::BadOperand { ref token, line: 2 }) if token == "xyz"), "{:?}", result.err());
+        let result = load_source("PSHF 1.5.2");
+        assert!(matches!(result, Err(VmError::BadOperand { ref token, line: 1 }) if token == "1.5.2"), "{:?}", result.err());
+    }
+
+    #[test]
+    fn underscores_separate_digits() {
+        let (vm, result, _) = run_source("PSH 1_000\nPSH -1_000_000\nPSH 0xFF_FF\nPSH 0b1010_1010\nPSH 1__0_");
+        result.unwrap();
+        assert_eq!(ints(&vm), [1000, -1_000_000, 0xFFFF, 0b1010_1010, 10]);
+        for token in ["_1", "-_1", "1_x"] {
+            let result = load_source(&format!("PSH {}", token));
+            assert!(matches!(result, Err(VmError::BadOperand { .. })), "{}: {:?}", token, result.err());
+        }
+    }
+
+    #[test]
+    fn aliases_assemble_like_canonical_mnemonics() {
+        let aliases = "push 3\nSET 0\nPUSHF 2.5\nPOP\nloop:\nGET 0\nPRINTPOP\nDEC 0\nGET 0\nJNZP loop\nPSH 'A'\nPRINTCHAR\nPUSH 16\nStore 5\nLOAD 5\nSQRT\nCLEARMEM\nCLEARSTACK\nHALT";
+        let canonical = "PSH 3\nSET 0\nPSHF 2.5\nPOP\nloop:\nGET 0\nPPT\nDEC 0\nGET 0\nJNZP loop\nPSH 'A'\nPRC\nPSH 16\nSTR 5\nLOA 5\nSQR\nMCL\nSCL\nHLT";
+        let (aliased_vm, result, aliased_output) = run_source(aliases);
+        result.unwrap();
+        let (canonical_vm, result, canonical_output) = run_source(canonical);
+        result.unwrap();
+        assert_eq!(aliased_vm.disassemble(), canonical_vm.disassemble());
+        assert_eq!(aliased_output, canonical_output);
+        assert_eq!(aliased_output, "3\n2\n1\nA");
+    }
+
+    #[test]
+    fn exec_line_keeps_state_between_lines() {
+        let mut vm = VM::new().with_output(std::io::sink());
+        vm.exec_line("PSH 2; PSH 3").unwrap();
+        vm.exec_line("ADD; SET 0 // five").unwrap();
+        vm.exec_line("top: GET 0").unwrap_err();
+        vm.exec_line("top:").unwrap();
+        vm.exec_line("GET 0; DEC; JMP skip; PSH 100; skip:").unwrap();
+        assert_eq!(ints(&vm), [4]);
+        assert_eq!(vm.registers()[0], 5);
+        assert_eq!(vm.resolve_label("top"), Some(4));
+        assert_eq!(vm.resolve_label("skip"), Some(8));
+
+        // A line that doesn't parse adds nothing, a failing one keeps what it did before the error
+        let length = vm.disassemble().lines().count();
+        assert!(matches!(vm.exec_line("PSH 1; PSH two"), Err(VmError::BadOperand { .. })));
+        assert_eq!(vm.disassemble().lines().count(), length);
+        assert!(matches!(vm.exec_line("PSH 1; POP; POP; POP"), Err(VmError::StackUnderflow { .. })));
+        assert!(vm.stack().is_empty());
+        assert!(matches!(vm.exec_line(".define X 1"), Err(VmError::ParseError { line: 1, .. })));
+    }
+
+    #[test]
+    fn exec_line_with_limits_stops_a_runaway_line() {
+        let mut vm = VM::new();
+        vm.exec_line("PSH 1").unwrap();
+        let result = vm.exec_line_with_limits("loop:; JMP loop", Some(50), None);
+        assert!(matches!(result, Err(VmError::InstructionLimitExceeded { limit: 50, pc: 1 })), "{:?}", result);
+        vm.exec_line_with_limits("PSH 2", Some(50), None).unwrap();
+        assert_eq!(ints(&vm), [1, 2]);
+    }
+}
@@ -0,0 +1,101 @@
+use std::io::{Read, Write};
+
+use crate::{VmError, VM};
+
+// Memory image layout (all integers little-endian):
+//   magic "LVMM", version byte
+//   u32 cell count, then per nonzero cell: u32 address, i32 value
+const MAGIC: &[u8; 4] = b"LVMM";
+const VERSION: u8 = 1;
+const HEADER_SIZE: usize = MAGIC.len() + 1 + 4;
+const CELL_SIZE: usize = 8;
+
+impl VM {
+    // Writes every nonzero memory cell to the given file
+    pub fn dump_memory(&self, path: &str) -> Result<(), VmError> {
+        let cells: Vec<(usize, i32)> = self.memory.iter().copied().enumerate().filter(|&(_, value)| value != 0).collect();
+
+        let mut bytes = Vec::with_capacity(HEADER_SIZE + cells.len() * CELL_SIZE);
+        bytes.extend_from_slice(MAGIC);
+        bytes.push(VERSION);
+        bytes.extend_from_slice(&(cells.len() as u32).to_le_bytes());
+        for (address, value) in cells {
+            bytes.extend_from_slice(&(address as u32).to_le_bytes());
+            bytes.extend_from_slice(&value.to_le_bytes());
+        }
+
+        std::fs::File::create(path)?.write_all(&bytes)?;
+        Ok(())
+    }
+
+    // Replaces the whole memory with the cells stored by dump_memory, every other cell reads as 0
+    pub fn load_memory(&mut self, path: &str) -> Result<(), VmError> {
+        let mut bytes = Vec::new();
+        std::fs::File::open(path)?.read_to_end(&mut bytes)?;
+
+        if bytes.len() < HEADER_SIZE || &bytes[..MAGIC.len()] != MAGIC {
+            return Err(invalid("missing LeverVM memory image header"));
+        }
+        let version = bytes[MAGIC.len()];
+        if version != VERSION {
+            return Err(invalid(&format!("unsupported version {}", version)));
+        }
+        let count = u32::from_le_bytes(bytes[MAGIC.len() + 1..HEADER_SIZE].try_into().unwrap()) as usize;
+        let cells = &bytes[HEADER_SIZE..];
+        if cells.len() != count * CELL_SIZE {
+            return Err(invalid(&format!("expected {} cells, found {} bytes of cell data", count, cells.len())));
+        }
+
+        // Validate everything before touching memory so a bad file leaves it unchanged
+        let mut memory = Vec::new();
+        for cell in cells.chunks_exact(CELL_SIZE) {
+            let address = u32::from_le_bytes(cell[..4].try_into().unwrap()) as usize;
+            let value = i32::from_le_bytes(cell[4..].try_into().unwrap());
+            if address >= self.config.max_memory {
+                return Err(invalid(&format!("address {} is outside of memory", address)));
+            }
+            if address >= memory.len() {
+                memory.resize(address + 1, 0);
+            }
+            memory[address] = value;
+        }
+        self.memory = memory;
+        Ok(())
+    }
+}
+
+fn invalid(message: &str) -> VmError {
+    VmError::InvalidMemoryImage { message: message.to_string() }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_util::*;
+    use crate::VmConfig;
+
+    #[test]
+    fn memory_image_round_trip() {
+        let (vm, result, _) = run_source("PSH 7\nSTR 3\nPSH -1\nSTR 5000\nPSH 0\nSTR 6000");
+        result.unwrap();
+        let path = temp_directory().join("memory.lvmm");
+        let path = path.to_str().unwrap();
+        vm.dump_memory(path).unwrap();
+        assert_eq!(std::fs::metadata(path).unwrap().len(), (HEADER_SIZE + 2 * CELL_SIZE) as u64);
+
+        let (mut reloaded, _) = load_source("LOA 3\nLOA 5000\nLOA 4").unwrap();
+        reloaded.load_memory(path).unwrap();
+        reloaded.run().unwrap();
+        assert_eq!(ints(&reloaded), [7, -1, 0]);
+    }
+
+    #[test]
+    fn memory_image_outside_of_memory_is_rejected() {
+        let (vm, _, _) = run_source("PSH 7\nSTR 100");
+        let path = temp_directory().join("memory.lvmm");
+        let path = path.to_str().unwrap();
+        vm.dump_memory(path).unwrap();
+        let mut small = VM::with_config(VmConfig { max_memory: 50, ..VmConfig::default() }).unwrap();
+        assert!(matches!(small.load_memory(path), Err(VmError::InvalidMemoryImage { .. })));
+    }
+}
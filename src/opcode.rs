@@ -0,0 +1,217 @@
+// Each opcode has a fixed byte encoding used by bytecode files, new opcodes take the next unused value and are appended to Opcode::ALL
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+#[repr(u8)]
+pub enum Opcode {
+    
+    // Arithmetic 
+    ADD = 0, // Add's the two latest values on the stack, if there are two operands it adds those two provided registers and pushes it onto the stack
+    SUB = 1, // Subtracts the top value from the second-to-top value on the stack, if there are two operands it subtracts the second provided register from the first provided register and pushes it onto the stack
+    MUL = 2, // Mulitplies the two latest values on the stack, if there are two operands it multiplies those two provided registers and pushes it onto the stack
+    DIV = 3, // Divides the second-to-top value by the top value on the stack, if there are two operands it divides the first provided register by the second provided register and pushes it onto the stack
+    MOD = 4, // Finds the remainder of the second-to-top value divided by the top value on the stack, if there are two operands it finds the remainder of the first provided register divided by the second provided register and pushes it onto the stack
+    INC = 5, // Increment the latest value on the stack by one, if an operand is provided it increments the register
+    DEC = 6, // Decrement the latest value on the stack by one, if an operand is provided it decrements the register
+    NEG = 62, // Negates the latest value on the stack, if an operand is provided it negates the register
+    ABS = 63, // Replaces the latest value on the stack with its absolute value, if an operand is provided it does so for the register
+    MIN = 64, // Pushes the smaller of the two latest values on the stack, if there are two operands it pushes the smaller of those two provided registers
+    MAX = 65, // Pushes the larger of the two latest values on the stack, if there are two operands it pushes the larger of those two provided registers
+    POW = 85, // Raises the second-to-top value to the power of the top value, if there are two operands it raises the first provided register to the power of the second
+    SQR = 86, // Replaces the latest value on the stack with its integer square root, if an operand is provided it does so for the register
+    GCD = 87, // Pushes the greatest common divisor of the latest two values on the stack, if two operands are provided it does so for the two registers
+
+    // Bitwise
+    AND = 7, // Bitwise AND of the two latest values on the stack, if there are two operands it ANDs those two provided registers and pushes it onto the stack
+    OR = 8, // Bitwise OR of the two latest values on the stack, if there are two operands it ORs those two provided registers and pushes it onto the stack
+    XOR = 9, // Bitwise XOR of the two latest values on the stack, if there are two operands it XORs those two provided registers and pushes it onto the stack
+    NND = 88, // Bitwise NAND of the two latest values on the stack, if there are two operands it NANDs those two provided registers and pushes it onto the stack
+    NOR = 89, // Bitwise NOR of the two latest values on the stack, if there are two operands it NORs those two provided registers and pushes it onto the stack
+    XNR = 90, // Bitwise XNOR of the two latest values on the stack, if there are two operands it XNORs those two provided registers and pushes it onto the stack
+    SHL = 10, // Pops a shift amount and a value and pushes the value shifted left, if there are two operands it shifts the first provided register by the second provided register
+    SHR = 11, // Pops a shift amount and a value and pushes the value arithmetically shifted right (the sign bit is kept), if there are two operands it shifts the first provided register by the second provided register
+    NOT = 12, // Bitwise complement of the latest value on the stack, if an operand is provided it complements the register
+
+    // Floating Point
+    FADD = 50, // Adds the two latest float values on the stack
+    FSUB = 51, // Subtracts the top float value from the second-to-top float value
+    FMUL = 52, // Multiplies the two latest float values on the stack
+    FDIV = 53, // Divides the second-to-top float value by the top float value, dividing by zero gives infinity or NaN
+    ITOF = 54, // Converts the integer on top of the stack to a float
+    FTOI = 55, // Converts the float on top of the stack to an integer, rounding towards zero
+
+    // Stack Operations
+    PSH = 13, // Pushes the given value onto stack
+    PSHF = 49, // Pushes the given float literal onto the stack
+    POP = 14, // Pop the latest value from the stack
+    DUP = 15, // Duplicates the top of the stack and pushes it into the stack
+    SWP = 16, // Swaps the tow top elements on the stack
+    PEK = 66, // Pushes a copy of the value the given depth below the top of the stack, 0 being the top
+    ROT = 67, // Rotates the top three values on the stack, moving the third-from-top value to the top
+    OVER = 68, // Pushes a copy of the second-from-top value on the stack
+    DRP = 69, // Pops the given number of values from the stack at once
+    DPN = 75, // Duplicates the given number of values on top of the stack, keeping their order
+    SEL = 94, // Pops a condition, a then-value and an else-value, and pushes the then-value if the condition is nonzero or the else-value otherwise
+    LEN = 73, // Pushes the amount of values on the stack, counted before the push
+    SCL = 17, // Clears the entire stack
+
+    // Memory Operations
+    STR = 18, // Stores latest value on the stack in memory
+    LOA = 19, // Loads value at given adress from memory to the stack
+    STRI = 56, // Pops an address and then a value, and stores the value at that address in memory
+    LOAI = 57, // Pops an address and pushes the value at that address in memory
+    STRR = 58, // Stores the latest value on the stack in memory at the address held in the given register
+    LOAR = 59, // Loads the value at the address held in the given register from memory to the stack
+    MCL = 20, // Clears the entire heap
+    ALC = 77, // Reserves the given number of memory cells and pushes the address of the first one
+    FRE = 78, // Releases every region reserved by ALC
+    FIL = 95, // Pops a value and writes it to the given number of memory cells starting at the given address
+    MCP = 96, // Pops a length and copies that many memory cells from the second given address to the first, the regions may overlap
+
+    // Register Operations
+    MOV = 21, // Moves a value from one register to another
+    COP = 22, // Copies a value from one register to another
+    SET = 23, // Sets the latest value on the stack to the specified register
+    GET = 24, // Pushes the value in the register to the stack
+    GETI = 92, // Pops a register index and pushes the value in that register
+    SETI = 93, // Pops a register index and then a value, and stores the value in that register
+    SWR = 79, // Swaps the values of the two given registers
+    CLR = 80, // Sets the given register to 0, without an operand it sets every register to 0
+
+    // Jumps
+    JMP = 25, // Unconditional jump to label
+    JEZ = 26, // Jump if equal to zero to label
+    JNZ = 27, // Jump if not equal to zero to label
+    JGZ = 28, // Jump if greater than zero to label
+    JLZ = 29, // Jump if less than zero to label
+    JEZP = 81, // Pops the top of the stack and jumps to label if it was zero
+    JNZP = 82, // Pops the top of the stack and jumps to label if it was not zero
+    JGZP = 83, // Pops the top of the stack and jumps to label if it was greater than zero
+    JLZP = 84, // Pops the top of the stack and jumps to label if it was less than zero
+    JMR = 46, // Relative jump by the given offset from the current instruction, which may be negative
+    JMI = 47, // Pops the top of the stack and jumps to it as a program address
+    PCP = 72, // Pushes the program counter of this instruction onto the stack
+    CALL = 30, // Pushes the return address onto the call stack and jumps to label
+    RET = 31, // Pops the call stack and returns to the address after the matching CALL
+
+    // Comparison Operations
+    EQU = 32, // Push 1 if top two values are equal, 0 otherwise. If there are two operands it compares the two given registers and returns 1 if equal, 0 otherwise
+    NEQ = 33, // Push 1 if top two values are not equal, 0 otherwise. If there are two operands it compares the two given registers and returns 1 if not equal, 0 otherwise
+    GTH = 34, // Push 1 if second-to-top > top, 0 otherwise. If there are two operands it compares the two given registers and returns 1 if the first provided register is greater than the second provided register, 0 otherwise
+    LTH = 35, // Push 1 if second-to-top < top, 0 otherwise  If there are two operands it compares the two given registers and returns 1 if the first provided register is less than the second provided register, 0 otherwise
+    GTE = 36, // Push 1 if second-to-top >= top, 0 otherwise. If there are two operands it compares the two given registers and returns 1 if the first provided register is greater than or equal than the second provided register, 0 otherwise
+    LTE = 37, // Push 1 if second-to-top <= top, 0 otherwise. If there are two operands it compares the two given registers and returns 1 if the first provided register is less than or equal than the second provided register, 0 otherwise
+    ANL = 60, // Push 1 if both top two values are nonzero, 0 otherwise. If there are two operands it does the same for the two given registers
+    ORL = 61, // Push 1 if either of the top two values is nonzero, 0 otherwise. If there are two operands it does the same for the two given registers
+
+    // IO
+    INP = 38, // Gets input from the console and pushes it on to the stack
+    INPS = 74, // Reads a line of input and pushes a 0 followed by its characters in reverse, so the first character is on top
+    PRT = 39, // Print the last thing on the stack to the console
+    PPT = 40, // Prints the last thing on the stack to the console and pops it
+    PRU = 98, // Prints the integer on top of the stack as an unsigned 32-bit value without popping it
+    PRX = 99, // Prints the integer on top of the stack in hexadecimal without popping it
+    PRC = 41, // Prints the ASCII character on the top of the stack
+    PRS = 48, // Prints the characters in memory starting at the given address until a 0 cell
+
+    // Miscellaneous 
+    TIM = 42, // Pushes the amount of epoch seconds to the stack, wrapping around in 2038
+    TIMM = 97, // Pushes the amount of epoch milliseconds as two values, the high 32 bits and then the low 32 bits
+    RND = 71, // Pushes a pseudo-random value, if there are two operands it is in the range [first operand, second operand)
+    DEB = 43, // Prints the PC, stack and memory to the console
+    DMP = 70, // Prints the given number of memory cells starting at the given address
+    HLT = 44, // Halts execution of the program
+    EXT = 76, // Pops the top of the stack and halts with it as the exit code
+    NOP = 45, // No operation is executed
+    LBL = 91, // Does nothing like NOP, but defines its operand as a label at its own position
+}
+
+impl Opcode {
+    // Every opcode, in byte order
+    pub const ALL: [Opcode; 100] = [
+        Opcode::ADD, Opcode::SUB, Opcode::MUL, Opcode::DIV, Opcode::MOD, Opcode::INC, Opcode::DEC,
+        Opcode::AND, Opcode::OR, Opcode::XOR, Opcode::SHL, Opcode::SHR, Opcode::NOT, Opcode::PSH,
+        Opcode::POP, Opcode::DUP, Opcode::SWP, Opcode::SCL, Opcode::STR, Opcode::LOA, Opcode::MCL,
+        Opcode::MOV, Opcode::COP, Opcode::SET, Opcode::GET, Opcode::JMP, Opcode::JEZ, Opcode::JNZ,
+        Opcode::JGZ, Opcode::JLZ, Opcode::CALL, Opcode::RET, Opcode::EQU, Opcode::NEQ, Opcode::GTH,
+        Opcode::LTH, Opcode::GTE, Opcode::LTE, Opcode::INP, Opcode::PRT, Opcode::PPT, Opcode::PRC,
+        Opcode::TIM, Opcode::DEB, Opcode::HLT, Opcode::NOP, Opcode::JMR, Opcode::JMI, Opcode::PRS,
+        Opcode::PSHF,
+        Opcode::FADD, Opcode::FSUB, Opcode::FMUL, Opcode::FDIV, Opcode::ITOF, Opcode::FTOI,
+        Opcode::STRI, Opcode::LOAI, Opcode::STRR, Opcode::LOAR, Opcode::ANL, Opcode::ORL,
+        Opcode::NEG, Opcode::ABS, Opcode::MIN, Opcode::MAX, Opcode::PEK, Opcode::ROT, Opcode::OVER,
+        Opcode::DRP, Opcode::DMP, Opcode::RND, Opcode::PCP, Opcode::LEN, Opcode::INPS, Opcode::DPN,
+        Opcode::EXT, Opcode::ALC, Opcode::FRE, Opcode::SWR, Opcode::CLR, Opcode::JEZP, Opcode::JNZP,
+        Opcode::JGZP, Opcode::JLZP, Opcode::POW, Opcode::SQR, Opcode::GCD, Opcode::NND, Opcode::NOR,
+        Opcode::XNR, Opcode::LBL, Opcode::GETI, Opcode::SETI, Opcode::SEL, Opcode::FIL, Opcode::MCP,
+        Opcode::TIMM, Opcode::PRU, Opcode::PRX,
+    ];
+
+    pub fn to_u8(self) -> u8 {
+        self as u8
+    }
+
+    // Opcodes whose first operand is a jump target
+    pub fn is_jump(self) -> bool {
+        matches!(
+            self,
+            Opcode::JMP | Opcode::JEZ | Opcode::JNZ | Opcode::JGZ | Opcode::JLZ
+                | Opcode::JEZP | Opcode::JNZP | Opcode::JGZP | Opcode::JLZP | Opcode::CALL
+        )
+    }
+
+    // The numbers of operands the opcode accepts, checked by the loader. Binary operations take either
+    // no operands (stack form) or two registers, unary ones either nothing or a single register
+    pub fn operand_spec(self) -> &'static [usize] {
+        match self {
+            Opcode::ADD | Opcode::SUB | Opcode::MUL | Opcode::DIV | Opcode::MOD | Opcode::MIN | Opcode::MAX
+                | Opcode::POW | Opcode::GCD | Opcode::AND | Opcode::OR | Opcode::XOR | Opcode::NND | Opcode::NOR
+                | Opcode::XNR | Opcode::SHL | Opcode::SHR | Opcode::EQU | Opcode::NEQ | Opcode::GTH | Opcode::LTH
+                | Opcode::GTE | Opcode::LTE | Opcode::ANL | Opcode::ORL | Opcode::RND => &[0, 2],
+            Opcode::INC | Opcode::DEC | Opcode::NEG | Opcode::ABS | Opcode::SQR | Opcode::NOT | Opcode::CLR => &[0, 1],
+            Opcode::PSH | Opcode::PSHF | Opcode::PEK | Opcode::DRP | Opcode::DPN | Opcode::STR | Opcode::LOA
+                | Opcode::STRR | Opcode::LOAR | Opcode::ALC | Opcode::SET | Opcode::GET | Opcode::JMP | Opcode::JEZ
+                | Opcode::JNZ | Opcode::JGZ | Opcode::JLZ | Opcode::JEZP | Opcode::JNZP | Opcode::JGZP | Opcode::JLZP
+                | Opcode::JMR | Opcode::CALL | Opcode::PRS | Opcode::LBL => &[1],
+            Opcode::MOV | Opcode::COP | Opcode::SWR | Opcode::DMP | Opcode::FIL | Opcode::MCP => &[2],
+            Opcode::FADD | Opcode::FSUB | Opcode::FMUL | Opcode::FDIV | Opcode::ITOF | Opcode::FTOI | Opcode::POP
+                | Opcode::DUP | Opcode::SWP | Opcode::ROT | Opcode::OVER | Opcode::LEN | Opcode::SCL | Opcode::STRI
+                | Opcode::LOAI | Opcode::MCL | Opcode::FRE | Opcode::JMI | Opcode::PCP | Opcode::RET | Opcode::INP
+                | Opcode::INPS | Opcode::PRT | Opcode::PPT | Opcode::PRC | Opcode::TIM | Opcode::DEB | Opcode::HLT
+                | Opcode::EXT | Opcode::NOP | Opcode::GETI | Opcode::SETI | Opcode::SEL
+                | Opcode::TIMM | Opcode::PRU | Opcode::PRX => &[0],
+        }
+    }
+
+    // Returns None for bytes that don't encode an opcode
+    pub fn from_u8(byte: u8) -> Option<Opcode> {
+        Opcode::ALL.iter().copied().find(|opcode| opcode.to_u8() == byte)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn byte_encoding_round_trips() {
+        for (index, &opcode) in Opcode::ALL.iter().enumerate() {
+            assert_eq!(opcode.to_u8() as usize, index, "{:?} is out of byte order in Opcode::ALL", opcode);
+            assert_eq!(Opcode::from_u8(opcode.to_u8()), Some(opcode));
+        }
+        for byte in Opcode::ALL.len()..=u8::MAX as usize {
+            assert_eq!(Opcode::from_u8(byte as u8), None);
+        }
+    }
+
+    #[test]
+    fn all_lists_every_variant() {
+        // Count the `NAME = byte,` lines of the enum so a variant missing from ALL is caught
+        let source = include_str!("opcode.rs");
+        let start = source.find("pub enum Opcode {").unwrap();
+        let end = start + source[start..].find("\n}").unwrap();
+        let variants = source[start..end]
+            .lines()
+            .filter(|line| line.trim_start().split_once(" = ").is_some_and(|(name, _)| name.chars().all(|c| c.is_ascii_uppercase())))
+            .count();
+        assert_eq!(Opcode::ALL.len(), variants);
+    }
+}
@@ -0,0 +1,79 @@
+// Helpers shared by the unit tests
+
+use std::cell::RefCell;
+use std::io::Write;
+use std::path::PathBuf;
+use std::rc::Rc;
+use std::sync::atomic::{AtomicUsize, Ordering};
+
+use crate::{Instruction, VmConfig, VmError, VM};
+
+// An output sink that stays readable after the VM took ownership of a clone of it
+#[derive(Clone, Default)]
+pub struct SharedOutput(Rc<RefCell<Vec<u8>>>);
+
+impl SharedOutput {
+    pub fn contents(&self) -> String {
+        String::from_utf8(self.0.borrow().clone()).unwrap()
+    }
+}
+
+impl Write for SharedOutput {
+    fn write(&mut self, buf: &[u8]) -> std::io::Result<usize> {
+        self.0.borrow_mut().write(buf)
+    }
+
+    fn flush(&mut self) -> std::io::Result<()> {
+        Ok(())
+    }
+}
+
+// Creates a new directory in the temp directory for one test's source files
+pub fn temp_directory() -> PathBuf {
+    static NEXT_DIRECTORY: AtomicUsize = AtomicUsize::new(0);
+    let directory = std::env::temp_dir().join(format!("levervm-test-{}-{}", std::process::id(), NEXT_DIRECTORY.fetch_add(1, Ordering::Relaxed)));
+    std::fs::create_dir_all(&directory).unwrap();
+    directory
+}
+
+// Writes source to a file of its own and returns its path
+pub fn source_file(source: &str) -> String {
+    let path = temp_directory().join("main.vm");
+    std::fs::write(&path, source).unwrap();
+    path.to_str().unwrap().to_string()
+}
+
+// Loads assembly source into a VM whose output is captured
+pub fn load_source(source: &str) -> Result<(VM, SharedOutput), VmError> {
+    load_source_with(VmConfig::default(), source)
+}
+
+pub fn load_source_with(config: VmConfig, source: &str) -> Result<(VM, SharedOutput), VmError> {
+    let output = SharedOutput::default();
+    let mut vm = VM::with_config(config)?.with_output(output.clone());
+    vm.load_program_from_file(&source_file(source))?;
+    Ok((vm, output))
+}
+
+// Loads and runs assembly source, returning the VM, the run result and everything it printed
+pub fn run_source(source: &str) -> (VM, Result<(), VmError>, String) {
+    run_source_with(VmConfig::default(), source)
+}
+
+pub fn run_source_with(config: VmConfig, source: &str) -> (VM, Result<(), VmError>, String) {
+    let (mut vm, output) = load_source_with(config, source).unwrap();
+    let result = vm.run();
+    (vm, result, output.contents())
+}
+
+pub fn run_program(program: Vec<Instruction>) -> (VM, Result<(), VmError>) {
+    let mut vm = VM::new().with_output(std::io::sink());
+    vm.load_program(program);
+    let result = vm.run();
+    (vm, result)
+}
+
+// The stack as plain integers, for VMs that only hold integers
+pub fn ints(vm: &VM) -> Vec<i32> {
+    vm.stack().iter().map(|value| value.as_int().expect("integer on the stack")).collect()
+}
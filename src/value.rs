@@ -0,0 +1,35 @@
+use std::fmt;
+
+// A single stack element. Integer opcodes only accept Int and float opcodes only accept Float,
+// mixing them is a TypeMismatch error
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Value {
+    Int(i32),
+    Float(f32),
+}
+
+impl Value {
+    pub fn as_int(self) -> Option<i32> {
+        match self {
+            Value::Int(value) => Some(value),
+            Value::Float(_) => None,
+        }
+    }
+
+    pub fn as_float(self) -> Option<f32> {
+        match self {
+            Value::Float(value) => Some(value),
+            Value::Int(_) => None,
+        }
+    }
+}
+
+impl fmt::Display for Value {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Value::Int(value) => write!(f, "{}", value),
+            // Debug formatting keeps the decimal point, so 5.0 doesn't print like the integer 5
+            Value::Float(value) => write!(f, "{:?}", value),
+        }
+    }
+}
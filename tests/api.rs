@@ -0,0 +1,45 @@
+use std::path::PathBuf;
+
+use virtual_machine::{Opcode, Value, VM};
+
+// Writes a program to a file of its own in the temp directory
+fn source_file(name: &str, source: &str) -> PathBuf {
+    let path = std::env::temp_dir().join(format!("levervm-api-{}-{}.vm", std::process::id(), name));
+    std::fs::write(&path, source).unwrap();
+    path
+}
+
+#[test]
+fn runs_a_program_built_in_rust() {
+    let mut vm = VM::new();
+    vm.load_program(vec![
+        (Opcode::PSH, Some(6), None),
+        (Opcode::PSH, Some(7), None),
+        (Opcode::MUL, None, None),
+        (Opcode::HLT, None, None),
+    ]);
+    vm.run().unwrap();
+    assert_eq!(vm.stack(), &[Value::Int(42)]);
+    assert_eq!(vm.exit_code(), Some(0));
+}
+
+#[test]
+fn runs_a_program_loaded_from_a_file() {
+    let path = source_file("countdown", "PSH 3\nloop:\nDEC\nJNZ loop\nPSH 10\nADD\nHLT\n");
+    let mut vm = VM::new();
+    vm.load_program_from_file(path.to_str().unwrap()).unwrap();
+    vm.run().unwrap();
+    assert_eq!(vm.stack(), &[Value::Int(10)]);
+}
+
+#[test]
+fn reads_results_through_the_stack_accessors() {
+    let mut vm = VM::new();
+    assert_eq!(vm.top(), None);
+    vm.load_program(vec![(Opcode::PSH, Some(2), None), (Opcode::PSHF, Some(2.5f32.to_bits() as i32), None)]);
+    vm.run().unwrap();
+    assert_eq!(vm.stack(), &[Value::Int(2), Value::Float(2.5)]);
+    assert_eq!(vm.top(), Some(Value::Float(2.5)));
+    assert_eq!(vm.top().and_then(Value::as_float), Some(2.5));
+    assert_eq!(vm.stack()[0].as_int(), Some(2));
+}
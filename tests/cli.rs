@@ -0,0 +1,101 @@
+use std::io::Write;
+use std::path::PathBuf;
+use std::process::{Command, Output, Stdio};
+
+// Writes a program to a file of its own in the temp directory
+fn source_file(name: &str, source: &str) -> PathBuf {
+    let path = std::env::temp_dir().join(format!("levervm-cli-{}-{}.vm", std::process::id(), name));
+    std::fs::write(&path, source).unwrap();
+    path
+}
+
+fn run(args: &[&str]) -> Output {
+    Command::new(env!("CARGO_BIN_EXE_virtual_machine")).args(args).output().unwrap()
+}
+
+fn stdout(output: &Output) -> String {
+    String::from_utf8(output.stdout.clone()).unwrap()
+}
+
+fn stderr(output: &Output) -> String {
+    String::from_utf8(output.stderr.clone()).unwrap()
+}
+
+#[test]
+fn runs_the_program_given_as_argument() {
+    let path = source_file("print", "PSH 5\nPRT\nHLT\n");
+    let output = run(&[path.to_str().unwrap()]);
+    assert_eq!(output.status.code(), Some(0));
+    assert_eq!(stdout(&output), "5\n");
+}
+
+#[test]
+fn limit_stops_a_runaway_program() {
+    let path = source_file("runaway", "loop:\nJMP loop\n");
+    let output = run(&[path.to_str().unwrap(), "--limit", "100"]);
+    assert_eq!(output.status.code(), Some(1));
+    assert!(stderr(&output).contains("Instruction limit of 100 exceeded"), "{}", stderr(&output));
+}
+
+#[test]
+fn seed_makes_rnd_repeatable() {
+    let path = source_file("random", "RND 0 1000000\nPPT\n");
+    let first = run(&[path.to_str().unwrap(), "--seed", "42"]);
+    let second = run(&["--seed", "42", path.to_str().unwrap()]);
+    assert_eq!(stdout(&first), stdout(&second));
+    assert_ne!(stdout(&first), stdout(&run(&[path.to_str().unwrap(), "--seed", "43"])));
+}
+
+#[test]
+fn debug_traces_to_stderr() {
+    let path = source_file("debug", "PSH 1\nPPT\n");
+    let output = run(&[path.to_str().unwrap(), "--debug"]);
+    assert_eq!(stdout(&output), "1\n");
+    assert_eq!(stderr(&output), "    0: PSH []\n    1: PPT [1]\n");
+}
+
+#[test]
+fn bad_arguments_are_usage_errors() {
+    for args in [&["--limit"][..], &["--limit", "many"], &["--verbose"], &["a.vm", "b.vm"]] {
+        let output = run(args);
+        assert_eq!(output.status.code(), Some(2), "{:?}", args);
+        assert!(stderr(&output).contains("Usage:"), "{:?}", args);
+    }
+}
+
+#[test]
+fn load_errors_exit_with_1() {
+    let path = source_file("unknown", "PHS 1\n");
+    let output = run(&[path.to_str().unwrap()]);
+    assert_eq!(output.status.code(), Some(1));
+    assert!(stderr(&output).contains("Unknown opcode 'PHS' on line 1"), "{}", stderr(&output));
+}
+
+#[test]
+fn exit_code_comes_from_ext() {
+    let path = source_file("exit", "PSH 3\nEXT\n");
+    assert_eq!(run(&[path.to_str().unwrap()]).status.code(), Some(3));
+}
+
+#[test]
+fn repl_runs_lines_with_the_limit() {
+    let mut child = Command::new(env!("CARGO_BIN_EXE_virtual_machine"))
+        .args(["--repl", "--limit", "50"])
+        .stdin(Stdio::piped())
+        .stdout(Stdio::piped())
+        .stderr(Stdio::piped())
+        .spawn()
+        .unwrap();
+    child.stdin.take().unwrap().write_all(b"PSH 1; PSH 2\nloop:; JMP loop\nADD\n").unwrap();
+    let output = child.wait_with_output().unwrap();
+    assert_eq!(output.status.code(), Some(0));
+    assert_eq!(stdout(&output), "> [1, 2]\n> [1, 2]\n> [3]\n> \n");
+    assert!(stderr(&output).contains("Instruction limit of 50 exceeded"), "{}", stderr(&output));
+}
+
+#[test]
+fn repl_rejects_a_program_path() {
+    let output = run(&["--repl", "program.vm"]);
+    assert_eq!(output.status.code(), Some(2));
+    assert!(stderr(&output).contains("--repl doesn't run a file"), "{}", stderr(&output));
+}